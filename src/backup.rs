@@ -0,0 +1,157 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::Local;
+
+use crate::error::{Error, Result};
+use crate::filesystem;
+use crate::superuser::{Method, Superuser};
+
+
+#[derive(Debug)]
+pub struct Backups {
+    dir: PathBuf,
+    run: String,
+}
+
+// a file saved during a run, along with how it was elevated if it was
+#[derive(Debug)]
+pub struct Saved {
+    pub path: PathBuf,
+    pub su: Option<Method>,
+}
+
+
+impl Backups {
+    pub fn new(state_dir: &Path) -> Self {
+        // runs starting within the same second must not share a backup area
+        let now = Local::now().format("%Y%m%d-%H%M%S%.3f");
+        Self { dir: backups_dir(state_dir),
+               run: format!("{}-{}", now, process::id()) }
+    }
+
+    pub fn path_for(&self, path: &Path) -> PathBuf {
+        stored_path(&self.dir.join(&self.run), path)
+    }
+
+    // copy `path` into this run's backup area before it gets overwritten
+    pub fn save(&self, path: &Path, su: Option<&Superuser>)
+        -> io::Result<()>
+    {
+        let path = std::path::absolute(path)?;
+        let backup = self.path_for(&path);
+        if backup.symlink_metadata().is_ok() {
+            // keep the state from before the first overwrite in this run
+            return Ok(());
+        }
+        fs::create_dir_all(backup.parent().unwrap())?;
+        filesystem::copy_all(&path, &backup, su)?;
+
+        let mut manifest = OpenOptions::new().create(true).append(true)
+                                             .open(manifest(&self.dir,
+                                                            &self.run))?;
+        match su {
+            None => writeln!(manifest, "{}", path.display()),
+            Some(su) => writeln!(manifest, "{}\t{}", path.display(),
+                                 su.method().program()),
+        }
+    }
+}
+
+
+impl Saved {
+    // a manifest line, with the escalation method after a tab for files
+    // backed up as the superuser
+    fn parse(line: &str) -> Self {
+        let su = line.rsplit_once('\t').and_then(|(path, program)| {
+            Some((path, Method::from_program(program)?))
+        });
+        match su {
+            Some((path, method)) =>
+                Self { path: PathBuf::from(path), su: Some(method) },
+            None => Self { path: PathBuf::from(line), su: None },
+        }
+    }
+}
+
+
+pub fn runs(state_dir: &Path) -> Vec<(String, Vec<Saved>)> {
+    let dir = backups_dir(state_dir);
+    let mut runs = fs::read_dir(&dir).into_iter().flatten()
+                      .filter_map(io::Result::ok)
+                      .filter_map(|e| e.file_name().into_string().ok())
+                      .map(|run| {
+                          let files = fs::read_to_string(manifest(&dir, &run))
+                                         .unwrap_or_default()
+                                         .lines().map(Saved::parse)
+                                         .collect();
+                          (run, files)
+                      })
+                      .collect::<Vec<_>>();
+    runs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    runs
+}
+
+
+// put back the most recent backup of `path`
+pub fn restore_file(state_dir: &Path, path: &Path) -> Result<String> {
+    let path = std::path::absolute(path).map_err(|e| Error::io(path, e))?;
+    let (run, saved) = runs(state_dir).into_iter().rev()
+                             .find_map(|(run, files)| {
+                                 let saved = files.into_iter()
+                                                  .find(|s| s.path == path)?;
+                                 Some((run, saved))
+                             })
+                             .ok_or_else(|| {
+                                 Error::NoBackup(path.display().to_string())
+                             })?;
+    restore(&backups_dir(state_dir).join(&run), &saved)?;
+    Ok(run)
+}
+
+
+pub fn restore_run(state_dir: &Path, run: &str) -> Result<Vec<PathBuf>> {
+    let (_, files) = runs(state_dir).into_iter()
+                                    .find(|(r, _)| r == run)
+                                    .ok_or_else(|| {
+                                        Error::NoBackup(run.to_owned())
+                                    })?;
+    for saved in &files {
+        restore(&backups_dir(state_dir).join(run), saved)?;
+    }
+    Ok(files.into_iter().map(|s| s.path).collect())
+}
+
+
+// files backed up as the superuser are root-owned, as is likely where they
+// go back to, so they are restored the same way
+fn restore(run_dir: &Path, saved: &Saved) -> Result<()> {
+    let path = &saved.path;
+    let backup = stored_path(run_dir, path);
+    let su = saved.su.map(Superuser::new);
+    let su = su.as_ref();
+    if path.symlink_metadata().is_ok() {
+        filesystem::remove(path, su).map_err(|e| Error::io(path, e))?;
+    }
+    filesystem::create_valid_parent(path, su)
+               .and_then(|_| filesystem::copy_all(&backup, path, su))
+               .map_err(|e| Error::io(path, e))
+}
+
+
+fn backups_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join("backups")
+}
+
+
+fn manifest(dir: &Path, run: &str) -> PathBuf {
+    dir.join(run).join("manifest")
+}
+
+
+fn stored_path(run_dir: &Path, path: &Path) -> PathBuf {
+    run_dir.join("files").join(path.strip_prefix("/").unwrap_or(path))
+}
+
@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Declarative dotfile and system provisioning")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Debug, Args)]
+pub struct Options {
+    /// Directory holding config.yaml [default: $ZAPP_CONFIG_DIR, else the
+    /// closest one up from here with a config.yaml, else ~/.config/zapp]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+    /// Deploy every destination under this directory instead of `/`
+    #[arg(long, global = true, value_name = "DIR")]
+    pub root: Option<PathBuf>,
+    /// Expand `~` in destinations to this directory
+    #[arg(long, global = true, value_name = "DIR")]
+    pub home: Option<PathBuf>,
+    /// Set a param, with dots for nested keys and `key+=value` to append to
+    /// a list, or merge in a param file [env: ZAPP_PARAM_<KEY>, with `__`
+    /// between nested keys]
+    #[arg(short = 'e', long = "param", global = true,
+          value_name = "KEY=VALUE|@FILE")]
+    pub overrides: Vec<Override>,
+    /// Ignore unknown task entries and fields instead of rejecting them
+    #[arg(long, global = true)]
+    pub no_strict: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the selected tasks
+    Apply(Apply),
+    /// Print the selected task tree
+    List(Selection),
+    /// Compare deployed files against what the config would produce
+    Status(Selection),
+    /// Load the config and resolve the selected tasks without running them
+    Check(Selection),
+    /// Check that every source exists and every template renders, for CI
+    Validate(Selection),
+    /// Remove managed files that are no longer declared by any task
+    Prune {
+        /// List the files that would be removed without removing them
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Put back files saved before zapp overwrote them
+    Restore(Restore),
+}
+
+#[derive(Debug, Default, Args)]
+pub struct Apply {
+    #[command(flatten)]
+    pub selection: Selection,
+    /// Report what each task would do without changing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Show a unified diff before overwriting copied or templated files
+    #[arg(short, long)]
+    pub diff: bool,
+    /// Show the diff and ask before overwriting each file
+    #[arg(short, long, conflicts_with = "dry_run")]
+    pub confirm: bool,
+    /// Keep running the remaining tasks of a group after one of them fails
+    #[arg(short, long)]
+    pub keep_going: bool,
+    /// How to report the progress of the run
+    #[arg(short, long, value_enum, default_value_t,
+          conflicts_with_all = ["diff", "confirm"])]
+    pub output: Format,
+    /// Only print the tasks that failed
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Show resolved paths and commands, and with -vv shell output too
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Also write a JUnit XML report of the run to this file
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Indented task tree followed by a summary
+    #[default]
+    Text,
+    /// One JSON object per line for every task start and finish
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub enum Override {
+    Value { key: String, value: String },
+    File(PathBuf),
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("target").required(true)
+                                       .args(["path", "run", "list"])))]
+pub struct Restore {
+    /// Restore the most recent backup of this file
+    pub path: Option<String>,
+    /// Restore every file backed up during the given run
+    #[arg(long)]
+    pub run: Option<String>,
+    /// List the recorded runs and their backed up files
+    #[arg(long)]
+    pub list: bool,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct Selection {
+    /// Task groups or nested task paths to select (e.g. `shell/zsh`)
+    #[arg(value_name = "TASK")]
+    pub tasks: Vec<String>,
+}
+
+
+impl Cli {
+    pub fn command(&mut self) -> Command {
+        self.command.take()
+                    .unwrap_or_else(|| Command::Apply(Apply::default()))
+    }
+}
+
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        if let Some(file) = arg.strip_prefix('@') {
+            return Ok(Self::File(PathBuf::from(file)));
+        }
+        match arg.split_once('=') {
+            Some((key, value)) if !key.is_empty() =>
+                Ok(Self::Value { key: key.to_owned(),
+                                 value: value.to_owned() }),
+            _ => Err("expected KEY=VALUE or @FILE".to_owned()),
+        }
+    }
+}
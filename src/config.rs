@@ -1,82 +1,512 @@
-use std::fs;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 use tera::{Context, Tera};
 
+use crate::backup::Backups;
+use crate::cli::{Format, Override};
+use crate::error::{Error, Result};
+use crate::facts::Facts;
+use crate::render::Verbosity;
+use crate::report::Report;
+use crate::source::{Key, Source};
+use crate::state::Ledger;
 use crate::task::Task;
-use crate::filesystem;
+use crate::filesystem::{self, Target};
+use crate::superuser::{Method, Superuser};
 
 
-lazy_static! {
-    static ref CONFIG_DIR: PathBuf = {
-        let mut conf_dir = dirs::config_dir().unwrap();
-        conf_dir.push("zapp");
-        conf_dir
-    };
+// directory holding config.yaml and the tasks, files, templates and params
+// it refers to
+#[derive(Clone, Debug)]
+pub struct ConfigDir(PathBuf);
 
-    pub static ref TEMPLATES: Tera = {
-        let mut templates_dir = CONFIG_DIR.clone();
-        templates_dir.push("templates/**/*");
-        Tera::new(templates_dir.to_str().unwrap()).unwrap()
-    };
+// which param file set each key, and the overrides of one file by another
+#[derive(Default)]
+struct Origins {
+    files: HashMap<Vec<String>, String>,
+    overrides: Vec<String>,
 }
 
-
 #[derive(Debug)]
 pub struct Params {
     pub context: Context,
-    pub depth: usize,
+    pub templates: Tera,
+    pub path: Vec<String>,
+    pub dry_run: bool,
+    pub diff: bool,
+    pub confirm: bool,
+    pub keep_going: bool,
+    pub elevated: bool,
+    pub backups: Backups,
+    pub ledger: Ledger,
+    pub report: Report,
+    pub config_dir: ConfigDir,
+    pub target: Target,
+    superuser: Superuser,
 }
 
 
 impl Params {
-    pub fn new(context: Context) -> Self {
-        Self { context, depth: 0 }
+    pub fn new(context: Context, templates: Tera, superuser: Superuser,
+               state_dir: &Path, ledger: Ledger, config_dir: ConfigDir,
+               target: Target) -> Self
+    {
+        Self { context, templates, path: Vec::new(), dry_run: false,
+               diff: false, confirm: false, keep_going: false,
+               elevated: false, backups: Backups::new(state_dir), ledger,
+               report: Report::new(Format::Text, false, Verbosity::Normal),
+               config_dir, target, superuser }
+    }
+
+    pub fn asset(&self, asset_dir: &str, asset_path: &str) -> PathBuf {
+        self.config_dir.asset(asset_dir, asset_path)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.path.len().saturating_sub(1)
+    }
+
+    // path of the running task as used for selection, without the root group
+    pub fn task_path(&self) -> String {
+        self.path.get(1..).unwrap_or_default().join("/")
+    }
+
+    pub fn superuser(&self) -> Option<&Superuser> {
+        if self.elevated { Some(&self.superuser) } else { None }
     }
 }
 
 
-pub fn asset(asset_dir: &str, asset_path: &str) -> PathBuf {
-    let asset_path = filesystem::expand_path(asset_path);
+impl ConfigDir {
+    // the first of the given directory, $ZAPP_CONFIG_DIR, the closest
+    // directory from the current one up holding a config.yaml, and
+    // ~/.config/zapp
+    pub fn locate(dir: Option<&Path>) -> Result<Self> {
+        let dir = dir.map(Path::to_owned)
+                     .or_else(|| {
+                         env::var_os("ZAPP_CONFIG_DIR")
+                             .filter(|d| !d.is_empty())
+                             .map(PathBuf::from)
+                     })
+                     .or_else(|| {
+                         env::current_dir().ok()?.ancestors()
+                             .find(|d| d.join("config.yaml").is_file())
+                             .map(Path::to_owned)
+                     })
+                     .or_else(|| dirs::config_dir().map(|d| d.join("zapp")))
+                     .ok_or(Error::NoConfigDir)?;
+        std::path::absolute(&dir).map(Self).map_err(|e| Error::io(dir, e))
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    pub fn asset(&self, asset_dir: &str, asset_path: &str) -> PathBuf {
+        let asset_path = filesystem::expand_path(asset_path);
+
+        if asset_path.is_absolute() {
+            asset_path
+        } else {
+            let mut path = self.0.clone();
+            path.push(asset_dir); path.push(asset_path);
+            path
+        }
+    }
+}
+
 
-    if asset_path.is_absolute() {
-        asset_path
-    } else {
-        let mut path = CONFIG_DIR.clone();
-        path.push(asset_dir); path.push(asset_path);
-        path
+// $XDG_STATE_HOME/zapp, falling back to ~/.local/state/zapp, or kept
+// inside the root given with --root so that its ledger and backups stay
+// apart from those of the real machine
+pub fn state_dir(target: &Target) -> Result<PathBuf> {
+    if let Some(root) = target.root() {
+        return Ok(root.join(".local/state/zapp"));
     }
+    let state_dir = env::var_os("XDG_STATE_HOME")
+                        .map(PathBuf::from)
+                        .filter(|p| p.is_absolute())
+                        .or_else(|| {
+                            dirs::home_dir().map(|h| h.join(".local/state"))
+                        })
+                        .ok_or(Error::NoStateDir)?;
+    Ok(state_dir.join("zapp"))
 }
 
 
-pub fn parse_config() -> (Params, Task) {
-    let mut conf_file = CONFIG_DIR.clone();
-    conf_file.push("config.yaml");
-    let file = fs::read_to_string(conf_file)
-                  .expect("unable to open config.yaml");
+pub fn parse_config(dir: ConfigDir, target: Target, strict: bool,
+                    overrides: &[Override])
+    -> Result<(Params, Task)>
+{
+    let source = Source::read(&dir.join("config.yaml"))?;
+    let config = source.parse()?;
+    let params_at = [Key::Field("params".to_owned())];
 
-    let config = serde_yaml::from_str::<Value>(&file)
-                            .expect("unable to parse config file");
+    let facts = serde_yaml::to_value(Facts::gather()).map_err(|e| {
+        source.error(&params_at, e.to_string())
+    })?;
+    let mut params = params(&dir, &source, &config["params"], facts)?;
+    override_params(&mut params, overrides)?;
+    let params = Value::Mapping(params);
 
-    let params = serde_yaml::from_str::<Value>(&param_strs(&config["params"]))
-                                   .expect("unable to parse param files");
+    let context = Context::from_serialize(&params).map_err(|e| {
+        source.error(&params_at, e.to_string())
+    })?;
+
+    let su_method_at = [Key::Field("su_method".to_owned())];
+    let su_method = match &config["su_method"] {
+        Value::Null => Method::default(),
+        // pkexec has no way of checking that an earlier authorization still
+        // holds, so it could end up prompting for every privileged command
+        Value::String(method) if method == "pkexec" =>
+            return Err(source.error(&su_method_at,
+                "pkexec is not supported as it cannot reuse credentials \
+                 across commands, use `sudo` or `doas`")),
+        method => source.deserialize(&su_method_at, method)?,
+    };
 
-    let context = Context::from_serialize(&params)
-                         .expect("unable to create params context");
+    let task = Task::parse_from_config(&dir, &source, &config["tasks"],
+                                       strict)?;
 
-    let task = Task::parse_from_config("main", &config["tasks"]);
+    let state_dir = state_dir(&target)?;
+    let ledger = Ledger::load(&state_dir, target.root())?;
 
-    (Params::new(context), task)
+    let params = Params::new(context, templates(&dir)?,
+                             Superuser::new(su_method), &state_dir, ledger,
+                             dir, target);
+    Ok((params, task))
 }
 
 
-fn param_strs(config: &Value) -> String {
-    config.as_sequence().unwrap().iter()
-          .map(Value::as_str).map(Option::unwrap)
-          .map(|s| asset("params", s))
-          .map(fs::read_to_string)
-          .collect::<Result<Vec<_>, _>>()
-          .expect("unable to load param files")
-          .join("\n")
+fn templates(dir: &ConfigDir) -> Result<Tera> {
+    let templates_dir = dir.join("templates");
+    let glob = templates_dir.join("**/*");
+    Tera::new(&glob.to_string_lossy())
+         .map_err(|e| Error::Template {
+             name: templates_dir.display().to_string(), source: e,
+         })
+}
+
+
+// param files deep-merged in order over the gathered `facts`, where later
+// files override earlier keys and replace lists unless the key ends in `+`,
+// which appends to them
+fn params(dir: &ConfigDir, source: &Source, config: &Value, facts: Value)
+    -> Result<Mapping>
+{
+    let at = [Key::Field("params".to_owned())];
+    let mut params = Mapping::new();
+    params.insert(Value::String("facts".to_owned()), facts);
+    let names = match config {
+        Value::Null => return Ok(params),
+        Value::Sequence(names) => names,
+        _ => return Err(source.error(&at, "expected a list of param files")),
+    };
+
+    let mut origins = Origins::default();
+    origins.files.insert(vec!["facts".to_owned()], "system facts".to_owned());
+    for (i, name) in names.iter().enumerate() {
+        let name = name.as_str().ok_or_else(|| {
+            source.error(&[&at[..], &[Key::Index(i)]].concat(),
+                         "expected a param file name")
+        })?;
+        let file = Source::read(&dir.asset("params", name))?;
+        match file.parse()? {
+            Value::Null => (),
+            Value::Mapping(values) => merge(&mut params, values, &[], &file,
+                                            Some(name), &mut origins)?,
+            _ => return Err(file.error(&[], "expected a mapping of params")),
+        }
+    }
+    for message in origins.overrides {
+        eprintln!("warning: {}", message);
+    }
+    Ok(params)
+}
+
+
+// ZAPP_PARAM_* variables, then the -e overrides in order, neither of which
+// warn about what they override
+fn override_params(params: &mut Mapping, overrides: &[Override])
+    -> Result<()>
+{
+    for (key, value) in env_params(env::vars_os()) {
+        set(params, &key, &value)?;
+    }
+
+    for param in overrides {
+        match param {
+            Override::Value { key, value } => set(params, key, value)?,
+            Override::File(path) => {
+                let file = Source::read(path)?;
+                match file.parse()? {
+                    Value::Null => (),
+                    Value::Mapping(values) => merge(params, values, &[],
+                                                    &file, None,
+                                                    &mut Origins::default())?,
+                    _ => return Err(file.error(&[],
+                                               "expected a mapping of params")),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// the dotted keys and values of ZAPP_PARAM_* variables, with `__` between
+// nested keys
+fn env_params<I>(vars: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item=(OsString, OsString)>,
+{
+    let mut params = vars.into_iter().filter_map(|(var, value)| {
+        let key = var.to_str()?.strip_prefix("ZAPP_PARAM_")?
+                     .to_lowercase().replace("__", ".");
+        Some((key, value.into_string().ok()?))
+    }).filter(|(key, _)| !key.is_empty()).collect::<Vec<_>>();
+    params.sort();
+    params
+}
+
+
+// set the dotted `key` to `value` read as yaml, appending to the list when
+// the key ends in `+`
+fn set(mut params: &mut Mapping, key: &str, value: &str) -> Result<()> {
+    let value = match serde_yaml::from_str(value) {
+        Ok(parsed) if !value.trim().is_empty() => parsed,
+        _ => Value::String(value.to_owned()),
+    };
+    let (dotted, append) = match key.strip_suffix('+') {
+        Some(key) => (key, true),
+        None => (key, false),
+    };
+
+    let mut keys = dotted.split('.').map(|k| Value::String(k.to_owned()))
+                      .collect::<Vec<_>>();
+    let last = keys.pop().unwrap_or_default();
+    for key in keys {
+        if !params.get(&key).is_some_and(Value::is_mapping) {
+            params.insert(key.clone(), Value::Mapping(Mapping::new()));
+        }
+        params = match params.get_mut(&key) {
+            Some(Value::Mapping(nested)) => nested,
+            _ => unreachable!(),
+        };
+    }
+
+    match (params.get_mut(&last), value) {
+        (Some(Value::Sequence(old)), Value::Sequence(new)) if append =>
+            old.extend(new),
+        (Some(Value::Sequence(old)), value) if append => old.push(value),
+        (Some(_), _) if append => return Err(Error::Param {
+            key: key.to_owned(),
+            message: format!("`{}` is not a list to append to", dotted),
+        }),
+        (None, value) if append && !value.is_sequence() => {
+            params.insert(last, Value::Sequence(vec![value]));
+        }
+        (_, value) => { params.insert(last, value); }
+    }
+    Ok(())
+}
+
+
+fn merge(params: &mut Mapping, values: Mapping, at: &[Key], file: &Source,
+         name: Option<&str>, origins: &mut Origins)
+    -> Result<()>
+{
+    for (key, value) in values {
+        let raw = key.as_str().unwrap_or_default().to_owned();
+        let (key, append) = match raw.strip_suffix('+') {
+            Some(stripped) => (Value::String(stripped.to_owned()), true),
+            None => (key, false),
+        };
+        let at = [at, &[Key::Field(raw.clone())]].concat();
+        let dotted = at.iter().filter_map(|k| match k {
+            Key::Field(k) => Some(k.strip_suffix('+').unwrap_or(k).to_owned()),
+            Key::Index(_) => None,
+        }).collect::<Vec<_>>();
+
+        if append && !value.is_sequence() {
+            return Err(file.error(&at, format!(
+                "`{}` appends to a list, expected a list", raw)));
+        }
+        // mappings stored as they are still need their own `+` keys resolved
+        let value = match value {
+            Value::Mapping(new)
+                    if !params.get(&key).is_some_and(Value::is_mapping) => {
+                let mut fresh = Mapping::new();
+                merge(&mut fresh, new, &at, file, name, origins)?;
+                Value::Mapping(fresh)
+            }
+            value => value,
+        };
+        match (params.get_mut(&key), value) {
+            (Some(Value::Mapping(old)), Value::Mapping(new)) if !append =>
+                merge(old, new, &at, file, name, origins)?,
+            (Some(Value::Sequence(old)), Value::Sequence(new)) if append =>
+                old.extend(new),
+            (Some(_), _) if append => return Err(file.error(&at, format!(
+                "`{}` appends to `{}`, which is not a list", raw,
+                dotted.join(".")))),
+            (Some(old), value) => {
+                if let Some(name) = name.filter(|_| *old != value) {
+                    let origin = (0..=dotted.len()).rev().find_map(|n| {
+                        origins.files.get(&dotted[..n])
+                    }).map_or("", String::as_str);
+                    origins.overrides.push(format!(
+                        "{} overrides `{}` set in {}", name, dotted.join("."),
+                        origin));
+                }
+                *old = value;
+                if let Some(name) = name {
+                    origins.files.insert(dotted, name.to_owned());
+                }
+            }
+            (None, value) => {
+                params.insert(key, value);
+                if let Some(name) = name {
+                    origins.files.insert(dotted, name.to_owned());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> Mapping {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    // merge the given param files in order, returning the override warnings
+    fn merged(files: &[(&str, &str)]) -> Result<(Mapping, Vec<String>)> {
+        let mut params = Mapping::new();
+        let mut origins = Origins::default();
+        for (name, text) in files {
+            let file = Source::new(Path::new(name), text.to_string());
+            merge(&mut params, yaml(text), &[], &file, Some(name),
+                  &mut origins)?;
+        }
+        Ok((params, origins.overrides))
+    }
+
+    #[test]
+    fn merges_nested_mappings() {
+        let (params, overrides) = merged(&[
+            ("a.yaml", "git: {name: a}"),
+            ("b.yaml", "git: {email: b@x}"),
+        ]).unwrap();
+        assert_eq!(params, yaml("git: {name: a, email: b@x}"));
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn later_files_override_and_replace_lists() {
+        let (params, overrides) = merged(&[
+            ("a.yaml", "{x: 1, l: [1, 2], git: {name: a}}"),
+            ("b.yaml", "{x: 2, l: [3], git: {name: b}}"),
+        ]).unwrap();
+        assert_eq!(params, yaml("{x: 2, l: [3], git: {name: b}}"));
+        assert_eq!(overrides, [
+            "b.yaml overrides `x` set in a.yaml",
+            "b.yaml overrides `l` set in a.yaml",
+            "b.yaml overrides `git.name` set in a.yaml",
+        ]);
+    }
+
+    #[test]
+    fn setting_the_same_value_is_no_override() {
+        let (_, overrides) = merged(&[("a.yaml", "x: 1"), ("b.yaml", "x: 1")])
+                                   .unwrap();
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn plus_suffix_appends_to_lists() {
+        let (params, overrides) = merged(&[
+            ("a.yaml", "l: [1]"),
+            ("b.yaml", "{l+: [2], new+: [3]}"),
+        ]).unwrap();
+        assert_eq!(params, yaml("{l: [1, 2], new: [3]}"));
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn plus_suffix_is_resolved_inside_new_mappings() {
+        let (params, _) = merged(&[
+            ("a.yaml", "x: 1"),
+            ("b.yaml", "{git: {aliases+: [co]}, x: {y+: [1]}}"),
+        ]).unwrap();
+        assert_eq!(params, yaml("{x: {y: [1]}, git: {aliases: [co]}}"));
+    }
+
+    #[test]
+    fn appending_to_a_non_list_is_rejected() {
+        let e = merged(&[("a.yaml", "x: 1"), ("b.yaml", "x+: [2]")])
+                      .unwrap_err();
+        assert!(e.to_string().starts_with(
+            "b.yaml:1:1: `x+` appends to `x`, which is not a list"));
+
+        let e = merged(&[("a.yaml", "l: [1]"), ("b.yaml", "l+: 2")])
+                      .unwrap_err();
+        assert!(e.to_string().contains("`l+` appends to a list"));
+    }
+
+    fn with(params: &str, overrides: &[(&str, &str)]) -> Result<Mapping> {
+        let mut params = yaml(params);
+        for (key, value) in overrides {
+            set(&mut params, key, value)?;
+        }
+        Ok(params)
+    }
+
+    #[test]
+    fn sets_dotted_keys_read_as_yaml() {
+        let params = with("{git: {name: a}, x: 1}", &[
+            ("git.email", "b@x"), ("x.y.z", "true"), ("n", "3"),
+            ("l", "[1, 2]"), ("empty", ""),
+        ]).unwrap();
+        assert_eq!(params, yaml("{git: {name: a, email: b@x}, \
+                                  x: {y: {z: true}}, n: 3, l: [1, 2], \
+                                  empty: ''}"));
+    }
+
+    #[test]
+    fn plus_suffix_appends_overrides_to_lists() {
+        let params = with("l: [1]", &[
+            ("l+", "2"), ("l+", "[3, 4]"), ("new+", "5"), ("more+", "[6]"),
+        ]).unwrap();
+        assert_eq!(params, yaml("{l: [1, 2, 3, 4], new: [5], more: [6]}"));
+    }
+
+    #[test]
+    fn appending_overrides_to_a_non_list_is_rejected() {
+        for value in ["z", "[z]"] {
+            let e = with("git: {name: y}", &[("git.name+", value)])
+                        .unwrap_err();
+            assert_eq!(e.to_string(), "param `git.name+`: `git.name` is not \
+                                       a list to append to");
+        }
+    }
+
+    #[test]
+    fn reads_nested_keys_from_the_environment() {
+        let vars = [("ZAPP_PARAM_GIT__EMAIL", "e"), ("ZAPP_PARAM_PROXY", "p"),
+                    ("ZAPP_PARAM_", "none"), ("PATH", "/bin")];
+        let params = env_params(vars.iter().map(|(k, v)| {
+            (OsString::from(k), OsString::from(v))
+        }));
+        assert_eq!(params, [("git.email".to_owned(), "e".to_owned()),
+                            ("proxy".to_owned(), "p".to_owned())]);
+    }
 }
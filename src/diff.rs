@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use similar::TextDiff;
+
+
+pub fn unified(path: &Path, old: &[u8], new: &[u8]) -> Option<String> {
+    if old == new {
+        return None;
+    }
+
+    let old = String::from_utf8_lossy(old);
+    let new = String::from_utf8_lossy(new);
+    let path = path.display().to_string();
+    Some(TextDiff::from_lines(&old, &new)
+                  .unified_diff()
+                  .header(&path, &format!("{} (new)", path))
+                  .to_string())
+}
+
+
+// show how `path` would change and, if `confirm` is set, ask before writing
+pub fn review(path: &Path, new: &[u8], confirm: bool) -> bool {
+    let old = match fs::read(path) {
+        Ok(old) => old,
+        Err(_) => {
+            println!("unable to read {} for diff", path.display());
+            return !confirm || prompt(path);
+        }
+    };
+
+    match unified(path, &old, new) {
+        None => true,
+        Some(diff) => {
+            print!("{}", diff);
+            !confirm || prompt(path)
+        }
+    }
+}
+
+
+fn prompt(path: &Path) -> bool {
+    print!("overwrite {}? [y/N] ", path.display());
+    let mut answer = String::new();
+    if io::stdout().flush().and(io::stdin().read_line(&mut answer)).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
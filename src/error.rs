@@ -0,0 +1,100 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, source: io::Error },
+    Yaml { path: PathBuf, source: serde_yaml::Error },
+    Located {
+        file: PathBuf, line: usize, column: usize, snippet: String,
+        message: String, hint: Option<String>,
+    },
+    Template { name: String, source: tera::Error },
+    Task { task: String, source: Box<Error> },
+    UnknownTask(String),
+    NoBackup(String),
+    Param { key: String, message: String },
+    NoConfigDir,
+    NoStateDir,
+    ForeignLedger(PathBuf),
+}
+
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io { path: path.into(), source }
+    }
+
+    pub fn in_task(self, task: String) -> Self {
+        match self {
+            Self::Task { .. } => self,
+            source => Self::Task { task, source: Box::new(source) },
+        }
+    }
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io { path, source } =>
+                write!(f, "{}: {}", path.display(), source),
+            Self::Yaml { path, source } =>
+                write!(f, "{}: {}", path.display(), source),
+            Self::Located { file, line, column, snippet, message, hint } => {
+                let pad = " ".repeat(line.to_string().len());
+                writeln!(f, "{}:{}:{}: {}", file.display(), line, column,
+                         message)?;
+                writeln!(f, "{} |", pad)?;
+                writeln!(f, "{} | {}", line, snippet)?;
+                write!(f, "{} | {: <2$}^", pad, "", column - 1)?;
+                match hint {
+                    Some(hint) => write!(f, "\n{} = hint: {}", pad, hint),
+                    None => Ok(()),
+                }
+            }
+            Self::Template { name, source } => {
+                // tera keeps the useful part of its messages in the causes
+                write!(f, "template {}", name)?;
+                let mut cause: Option<&dyn error::Error> = Some(source);
+                while let Some(e) = cause {
+                    write!(f, ": {}", e)?;
+                    cause = e.source();
+                }
+                Ok(())
+            }
+            Self::Task { task, source } =>
+                write!(f, "task {}: {}", task, source),
+            Self::UnknownTask(task) => write!(f, "unknown task: {}", task),
+            Self::NoBackup(what) => write!(f, "no backup found for {}", what),
+            Self::Param { key, message } =>
+                write!(f, "param `{}`: {}", key, message),
+            Self::NoConfigDir =>
+                write!(f, "no config directory found, use --config-dir"),
+            Self::NoStateDir =>
+                write!(f, "no state directory found, set XDG_STATE_HOME"),
+            Self::ForeignLedger(path) =>
+                write!(f, "{}: kept for a different --root", path.display()),
+        }
+    }
+}
+
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Yaml { source, .. } => Some(source),
+            Self::Task { source, .. } => Some(source.as_ref()),
+            Self::Located { .. } | Self::Template { .. }
+                | Self::UnknownTask(_) | Self::NoBackup(_) | Self::Param { .. }
+                | Self::NoConfigDir | Self::NoStateDir
+                | Self::ForeignLedger(_) => None,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::thread;
+
+use serde::Serialize;
+
+
+// what templates get to know about the machine, under `facts`
+#[derive(Debug, Serialize)]
+pub struct Facts {
+    hostname: Option<String>,
+    username: Option<String>,
+    uid: Option<u32>,
+    home: Option<String>,
+    shell: Option<String>,
+    // the fields of /etc/os-release, lowercased, e.g. `os.id`
+    os: BTreeMap<String, String>,
+    kernel: Option<String>,
+    arch: &'static str,
+    cpus: usize,
+}
+
+
+impl Facts {
+    pub fn gather() -> Self {
+        let uid = fs::metadata("/proc/self").ok().map(|m| m.uid())
+                     .or_else(|| command("id", "-u")?.parse().ok());
+        let account = uid.and_then(passwd);
+        let account = |field: usize| {
+            account.as_ref().map(|a| a[field].clone())
+                   .filter(|f| !f.is_empty())
+        };
+
+        Self {
+            hostname: read("/proc/sys/kernel/hostname")
+                          .or_else(|| command("uname", "-n")),
+            username: env::var("USER").ok().or_else(|| account(0)),
+            uid,
+            home: dirs::home_dir().map(|h| h.display().to_string()),
+            shell: env::var("SHELL").ok().or_else(|| account(6)),
+            os: os_release(),
+            kernel: read("/proc/sys/kernel/osrelease")
+                        .or_else(|| command("uname", "-r")),
+            arch: env::consts::ARCH,
+            cpus: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+
+fn command(program: &str, flag: &str) -> Option<String> {
+    let output = Command::new(program).arg(flag).output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned()).filter(|o| !o.is_empty())
+}
+
+
+// the fields of the /etc/passwd entry of `uid`
+fn passwd(uid: u32) -> Option<Vec<String>> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines()
+          .map(|l| l.split(':').map(str::to_owned).collect::<Vec<_>>())
+          .find(|f| f.len() == 7 && f[2] == uid.to_string())
+}
+
+
+fn os_release() -> BTreeMap<String, String> {
+    let release = read("/etc/os-release")
+                      .or_else(|| read("/usr/lib/os-release"))
+                      .unwrap_or_default();
+    release.lines().filter_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        let value = value.trim();
+        let value = value.strip_prefix('"')
+                         .and_then(|v| v.strip_suffix('"'))
+                         .or_else(|| {
+                             value.strip_prefix('\'')
+                                  .and_then(|v| v.strip_suffix('\''))
+                         })
+                         .unwrap_or(value);
+        Some((key.trim().to_lowercase(), value.replace("\\\"", "\"")))
+    }).filter(|(key, _)| !key.is_empty() && !key.starts_with('#'))
+      .collect()
+}
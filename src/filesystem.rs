@@ -1,10 +1,38 @@
+use std::ffi::OsStr;
 use std::fs::{self, Permissions};
 use std::io;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{self as unixfs, PermissionsExt};
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Deserializer, de::Error};
 
+use crate::superuser::Superuser;
+
+
+// where destinations end up, with `~` expanding to `home` and everything
+// rebased under `root` when given
+#[derive(Clone, Debug, Default)]
+pub struct Target {
+    root: Option<PathBuf>,
+    home: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileState {
+    Missing,
+    Modified,
+    WrongMode,
+    InSync,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkState {
+    Missing,
+    Stale,
+    Occupied,
+    Linked,
+}
+
 
 pub fn expand_path(path: &str) -> PathBuf {
     let path = shellexpand::tilde(path);
@@ -12,11 +40,155 @@ pub fn expand_path(path: &str) -> PathBuf {
 }
 
 
-pub fn create_valid_parent(path: &Path) {
-    let parent = path.parent().unwrap();
-    assert!(!parent.is_file());
-    if !parent.exists() {
-        fs::create_dir_all(parent).unwrap();
+impl Target {
+    pub fn new(root: Option<&Path>, home: Option<&Path>) -> io::Result<Self> {
+        Ok(Self { root: root.map(std::path::absolute).transpose()?,
+                  home: home.map(std::path::absolute).transpose()? })
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    // whether `path` is somewhere this target deploys to
+    pub fn contains(&self, path: &Path) -> bool {
+        self.root.as_ref().is_none_or(|root| path.starts_with(root))
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        let path = match &self.home {
+            Some(home) => PathBuf::from(&*shellexpand::tilde_with_context(
+                path, || Some(home))),
+            None => expand_path(path),
+        };
+        match &self.root {
+            None => path,
+            Some(root) => {
+                let path = std::path::absolute(&path).unwrap_or(path);
+                root.join(path.strip_prefix("/").unwrap_or(&path))
+            }
+        }
+    }
+}
+
+
+// `mode` is only compared when it is explicitly requested
+pub fn compare(path: &Path, contents: &[u8], mode: Option<u32>) -> FileState {
+    let metadata = match path.metadata() {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return FileState::Modified,
+        Err(_) => return FileState::Missing,
+    };
+
+    match fs::read(path) {
+        Ok(current) if current == contents => (),
+        _ => return FileState::Modified,
+    }
+
+    match mode {
+        Some(mode) if metadata.permissions().mode() & 0o7777 != mode =>
+            FileState::WrongMode,
+        _ => FileState::InSync,
+    }
+}
+
+
+pub fn compare_link(src: &Path, dst: &Path) -> LinkState {
+    match dst.symlink_metadata() {
+        Err(_) => LinkState::Missing,
+        Ok(metadata) if !metadata.file_type().is_symlink() =>
+            LinkState::Occupied,
+        Ok(_) => match fs::read_link(dst) {
+            Ok(target) if target == src => LinkState::Linked,
+            _ => LinkState::Stale,
+        },
+    }
+}
+
+
+pub fn create_valid_parent(path: &Path, su: Option<&Superuser>)
+    -> Result<(), io::Error>
+{
+    let parent = path.parent().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path has no parent")
+    })?;
+    if parent.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory,
+                                  format!("{} is not a directory",
+                                          parent.display())));
+    }
+    match (parent.exists(), su) {
+        (true, _) => Ok(()),
+        (false, None) => fs::create_dir_all(parent),
+        (false, Some(su)) => su.run([os("mkdir"), os("-p"),
+                                     parent.as_os_str()], None),
+    }
+}
+
+
+pub fn copy(src: &Path, dst: &Path, su: Option<&Superuser>)
+    -> Result<(), io::Error>
+{
+    match su {
+        None => fs::copy(src, dst).map(|_| ()),
+        Some(su) => su.run([os("cp"), src.as_os_str(), dst.as_os_str()],
+                           None),
+    }
+}
+
+
+// recursively copy `src` to `dst`, preserving symlinks and permissions
+pub fn copy_all(src: &Path, dst: &Path, su: Option<&Superuser>)
+    -> Result<(), io::Error>
+{
+    if let Some(su) = su {
+        return su.run([os("cp"), os("-a"), src.as_os_str(), dst.as_os_str()],
+                      None);
+    }
+
+    let metadata = src.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        unixfs::symlink(fs::read_link(src)?, dst)
+    } else if metadata.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &dst.join(entry.file_name()), None)?;
+        }
+        fs::set_permissions(dst, metadata.permissions())
+    } else {
+        fs::copy(src, dst).map(|_| ())
+    }
+}
+
+
+pub fn write(path: &Path, contents: &[u8], su: Option<&Superuser>)
+    -> Result<(), io::Error>
+{
+    match su {
+        None => fs::write(path, contents),
+        Some(su) => su.run([os("sh"), os("-c"), os("cat > \"$0\""),
+                            path.as_os_str()], Some(contents)),
+    }
+}
+
+
+pub fn symlink(src: &Path, dst: &Path, su: Option<&Superuser>)
+    -> Result<(), io::Error>
+{
+    match su {
+        None => unixfs::symlink(src, dst),
+        Some(su) => su.run([os("ln"), os("-s"), src.as_os_str(),
+                            dst.as_os_str()], None),
+    }
+}
+
+
+pub fn remove(path: &Path, su: Option<&Superuser>) -> Result<(), io::Error> {
+    match su {
+        None if path.symlink_metadata()?.is_dir() => fs::remove_dir_all(path),
+        None => fs::remove_file(path),
+        Some(su) => su.run([os("rm"), os("-rf"), path.as_os_str()], None),
     }
 }
 
@@ -39,12 +211,22 @@ where
 }
 
 
-pub fn set_permissions<P>(path: P, mode: Option<u32>) -> Result<(), io::Error>
+pub fn set_permissions<P>(path: P, mode: Option<u32>, su: Option<&Superuser>)
+    -> Result<(), io::Error>
 where
     P: AsRef<Path>
 {
-    match mode {
-        None => Ok(()),
-        Some(mode) => fs::set_permissions(path, Permissions::from_mode(mode)),
+    match (mode, su) {
+        (None, _) => Ok(()),
+        (Some(mode), None) => fs::set_permissions(path,
+                                                  Permissions::from_mode(mode)),
+        (Some(mode), Some(su)) => su.run([os("chmod"),
+                                          os(&format!("{:o}", mode)),
+                                          path.as_ref().as_os_str()], None),
     }
 }
+
+
+fn os(s: &str) -> &OsStr {
+    OsStr::new(s)
+}
@@ -1,14 +1,137 @@
-#[macro_use]
-extern crate lazy_static;
-
+mod backup;
+mod cli;
 mod config;
+mod diff;
+mod error;
+mod facts;
 mod filesystem;
+mod plan;
+mod prune;
+mod render;
+mod report;
+mod source;
+mod state;
+mod superuser;
 mod task;
 
-use task::Runnable;
+use std::process;
+
+use clap::Parser;
+
+use cli::{Cli, Command, Options, Restore};
+use config::{ConfigDir, Params};
+use error::{Error, Result};
+use filesystem::Target;
+use render::Verbosity;
+use report::Report;
+use task::Status;
 
 
 fn main() {
-    let (mut params, tasks) = config::parse_config();
-    tasks.run(&mut params);
+    let mut cli = Cli::parse();
+    match run(cli.command(), &cli.options) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+
+// returns whether the command completed without failures
+fn run(command: Command, options: &Options) -> Result<bool> {
+    let selection: &[String] = match &command {
+        Command::Apply(apply) => &apply.selection.tasks,
+        Command::List(selection) | Command::Status(selection)
+            | Command::Check(selection) | Command::Validate(selection) =>
+            &selection.tasks,
+        Command::Prune { .. } | Command::Restore(_) => &[],
+    };
+
+    let target = Target::new(options.root.as_deref(), options.home.as_deref())
+                        .map_err(|e| Error::io(".", e))?;
+    if let Command::Restore(restore) = &command {
+        return self::restore(restore, &target);
+    }
+
+    let config_dir = ConfigDir::locate(options.config_dir.as_deref())?;
+    let (mut params, tasks) = config::parse_config(config_dir, target,
+                                                   !options.no_strict,
+                                                   &options.overrides)?;
+    let tasks = tasks.select(selection)?;
+
+    match command {
+        Command::Apply(apply) => {
+            params.dry_run = apply.dry_run;
+            params.diff = apply.diff || apply.confirm;
+            params.confirm = apply.confirm;
+            params.keep_going = apply.keep_going;
+            params.report = Report::new(apply.output, apply.junit.is_some(),
+                                        Verbosity::new(apply.quiet,
+                                                       apply.verbose));
+            let status = tasks.run(&mut params);
+            params.report.print();
+            if let Some(junit) = &apply.junit {
+                params.report.write_junit(junit)
+                             .map_err(|e| Error::io(junit, e))?;
+            }
+            save_ledger(&params)?;
+            Ok(!matches!(status, Status::Failure(_)))
+        }
+        Command::List(_) => {
+            tasks.list(0);
+            Ok(true)
+        }
+        Command::Status(_) => Ok(tasks.status(&mut params)),
+        Command::Check(_) => {
+            println!("ok: {} tasks selected", tasks.count());
+            Ok(true)
+        }
+        Command::Validate(_) => {
+            let problems = tasks.validate(&mut params);
+            for e in &problems {
+                println!("error: {}", e);
+            }
+            if problems.is_empty() {
+                println!("ok: {} tasks validated", tasks.count());
+            } else {
+                println!("{} problems found", problems.len());
+            }
+            Ok(problems.is_empty())
+        }
+        Command::Prune { dry_run } => {
+            params.dry_run = dry_run;
+            let pruned = prune::prune(&mut params, &tasks);
+            save_ledger(&params)?;
+            Ok(pruned)
+        }
+        Command::Restore(_) => unreachable!(),
+    }
+}
+
+
+fn save_ledger(params: &Params) -> Result<()> {
+    if params.dry_run { Ok(()) } else { params.ledger.save() }
+}
+
+
+fn restore(restore: &Restore, target: &Target) -> Result<bool> {
+    let state_dir = config::state_dir(target)?;
+    if let Some(path) = &restore.path {
+        let path = target.path(path);
+        let run = backup::restore_file(&state_dir, &path)?;
+        println!("restored {} from {}", path.display(), run);
+    } else if let Some(run) = &restore.run {
+        for file in backup::restore_run(&state_dir, run)? {
+            println!("restored {}", file.display());
+        }
+    } else {
+        for (run, files) in backup::runs(&state_dir) {
+            println!("{}", run);
+            files.iter().for_each(|f| println!("  {}", f.path.display()));
+        }
+    }
+    Ok(true)
 }
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::filesystem::{FileState, LinkState};
+
+
+#[derive(Debug)]
+pub enum Action {
+    Create(PathBuf),
+    Overwrite(PathBuf),
+    Chmod(PathBuf, u32),
+    Unchanged(PathBuf),
+    Link { src: PathBuf, dst: PathBuf },
+    Relink { src: PathBuf, dst: PathBuf },
+    Replace { src: PathBuf, dst: PathBuf, backup: Option<PathBuf> },
+    Run(String),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Drift {
+    InSync,
+    Modified,
+    Missing,
+    WrongMode,
+    WrongTarget,
+}
+
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Create(dst) => write!(f, "create {}", dst.display()),
+            Self::Overwrite(dst) => write!(f, "overwrite {}", dst.display()),
+            Self::Chmod(dst, mode) =>
+                write!(f, "set mode {:o} on {}", mode, dst.display()),
+            Self::Unchanged(dst) =>
+                write!(f, "leave {} unchanged", dst.display()),
+            Self::Link { src, dst } =>
+                write!(f, "link {} -> {}", dst.display(), src.display()),
+            Self::Relink { src, dst } =>
+                write!(f, "relink {} -> {}", dst.display(), src.display()),
+            Self::Replace { src, dst, backup } => {
+                if let Some(backup) = backup {
+                    write!(f, "back up {} to {} and ", dst.display(),
+                           backup.display())?;
+                }
+                write!(f, "replace {} with link -> {}", dst.display(),
+                       src.display())
+            }
+            Self::Run(cmd) => write!(f, "run `{}`", cmd),
+        }
+    }
+}
+
+
+impl From<FileState> for Drift {
+    fn from(state: FileState) -> Self {
+        match state {
+            FileState::Missing => Self::Missing,
+            FileState::Modified => Self::Modified,
+            FileState::WrongMode => Self::WrongMode,
+            FileState::InSync => Self::InSync,
+        }
+    }
+}
+
+
+impl From<LinkState> for Drift {
+    fn from(state: LinkState) -> Self {
+        match state {
+            LinkState::Missing => Self::Missing,
+            LinkState::Stale => Self::WrongTarget,
+            LinkState::Occupied => Self::Modified,
+            LinkState::Linked => Self::InSync,
+        }
+    }
+}
+
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.pad(match self {
+            Self::InSync => "in-sync",
+            Self::Modified => "modified",
+            Self::Missing => "missing",
+            Self::WrongMode => "wrong mode",
+            Self::WrongTarget => "wrong target",
+        })
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeSet;
+
+use crate::config::Params;
+use crate::filesystem;
+use crate::state;
+use crate::task::Task;
+
+
+// remove managed files that no task in `tasks` deploys anymore, returning
+// whether each of them is either gone or was kept for having been modified
+pub fn prune(params: &mut Params, tasks: &Task) -> bool {
+    let declared = tasks.destinations(params).into_iter()
+                        .collect::<BTreeSet<_>>();
+    let stale = params.ledger.files.iter()
+                      .filter(|(path, _)| params.target.contains(path))
+                      .filter(|(path, _)| !declared.contains(*path))
+                      .map(|(path, entry)| (path.clone(), entry.clone()))
+                      .collect::<Vec<_>>();
+
+    let mut pruned = true;
+    for (path, entry) in stale {
+        let forget = if path.symlink_metadata().is_err() {
+            println!("{}: already gone", path.display());
+            true
+        } else if !state::untouched(&path, &entry.managed) {
+            println!("{}: modified since zapp wrote it, keeping",
+                     path.display());
+            false
+        } else if params.dry_run {
+            println!("{}: would remove", path.display());
+            false
+        } else {
+            params.elevated = entry.su;
+            let removed = filesystem::remove(&path, params.superuser());
+            params.elevated = false;
+            match &removed {
+                Ok(_) => println!("{}: removed", path.display()),
+                Err(e) => eprintln!("{}: unable to remove: {}",
+                                    path.display(), e),
+            }
+            pruned &= removed.is_ok();
+            removed.is_ok()
+        };
+
+        if forget && !params.dry_run {
+            params.ledger.files.remove(&path);
+        }
+    }
+    pruned
+}
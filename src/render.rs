@@ -0,0 +1,192 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::report::Outcome;
+use crate::task::Status;
+
+
+const SPINNER: &str = "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏";
+
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+// console output of the text format
+#[derive(Debug)]
+pub struct Renderer {
+    verbosity: Verbosity,
+    color: bool,
+    tty: bool,
+}
+
+// animates next to a running task until dropped
+pub struct Spinner {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+
+impl Verbosity {
+    pub fn new(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, 0) => Self::Normal,
+            (false, 1) => Self::Verbose,
+            (false, _) => Self::Debug,
+        }
+    }
+}
+
+
+impl Renderer {
+    pub fn new(verbosity: Verbosity) -> Self {
+        let tty = io::stdout().is_terminal();
+        Self { verbosity, tty,
+               color: tty && env::var_os("NO_COLOR").is_none() }
+    }
+
+    // whether shell output goes straight to the terminal as it is printed,
+    // which is also how interactive commands get to prompt on it
+    pub fn streams_output(&self, interactive: bool) -> bool {
+        self.verbosity == Verbosity::Debug || (interactive && self.tty)
+    }
+
+    pub fn header(&self, name: &str, depth: usize) {
+        if self.verbosity > Verbosity::Quiet {
+            println!("{: <1$}{name}", "", depth * 2,
+                     name=self.paint("1", name));
+        }
+    }
+
+    pub fn spin(&self, name: &str, depth: usize) -> Option<Spinner> {
+        if self.tty && self.verbosity > Verbosity::Quiet {
+            Some(Spinner::start(format!("{: <1$}{name}", "", depth * 2,
+                                        name=name)))
+        } else { None }
+    }
+
+    pub fn finish(&self, outcome: &Outcome) {
+        let failed = matches!(outcome.status, Status::Failure(_));
+        // groups were announced by their header already
+        if outcome.kind == "group"
+                || (self.verbosity == Verbosity::Quiet && !failed) {
+            return;
+        }
+
+        let (indent, name) = match self.verbosity {
+            Verbosity::Quiet => (0, outcome.task.as_str()),
+            _ => (outcome.depth * 2, outcome.name.as_str()),
+        };
+        println!("{: <1$}{name}: {outcome}", "", indent, name=name,
+                 outcome=self.outcome(outcome));
+
+        if self.verbosity >= Verbosity::Verbose {
+            if let Some(detail) = &outcome.detail {
+                println!("{: <1$}{detail}", "", indent + 2,
+                         detail=self.paint("2", detail));
+            }
+        }
+        if let Some(output) = outcome.output.as_deref() {
+            if failed || self.verbosity == Verbosity::Debug {
+                output.lines().for_each(|l| {
+                    println!("{: <1$}{line}", "", indent + 4, line=l)
+                });
+            }
+        }
+    }
+
+    pub fn summary(&self, lines: &[(&str, String)]) {
+        if self.verbosity > Verbosity::Quiet {
+            println!("\n{}", self.paint("1", "summary"));
+            for (label, value) in lines {
+                println!("  {:<10} {}", label, value);
+            }
+        }
+    }
+
+    pub fn failures(&self, failures: &[(&str, &str, bool)]) {
+        if self.verbosity > Verbosity::Quiet && !failures.is_empty() {
+            println!("\n{}", self.paint("1;31", "failed tasks"));
+            for (task, reason, ignored) in failures {
+                println!("  {}: {}{}", task, reason,
+                         if *ignored { " (ignored)" } else { "" });
+            }
+        }
+    }
+
+    fn outcome(&self, outcome: &Outcome) -> String {
+        let su = if outcome.su { " (as superuser)" } else { "" };
+        let ignored = if outcome.ignored { " (ignored)" } else { "" };
+        match (&outcome.status, &outcome.action) {
+            (_, Some(action)) => format!("would {}{}", action, su),
+            (Status::Failure(reason), None) if outcome.planned =>
+                format!("{}: {}{}", self.paint("31", "would fail"), reason,
+                        su),
+            (status @ Status::Failure(reason), None) =>
+                format!("{}{}: {}", self.status(status), ignored, reason),
+            (status, None) => self.status(status),
+        }
+    }
+
+    fn status(&self, status: &Status) -> String {
+        let color = match status {
+            Status::Changed => "33",
+            Status::Unchanged => "32",
+            Status::Failure(_) => "1;31",
+            Status::Skipped => "36",
+        };
+        self.paint(color, &status.to_string())
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", color, text)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+
+impl Spinner {
+    fn start(line: String) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let done = done.clone();
+            move || {
+                let mut stdout = io::stdout();
+                for frame in SPINNER.chars().cycle() {
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let _ = write!(stdout, "\r{} {}", line, frame);
+                    let _ = stdout.flush();
+                    thread::park_timeout(Duration::from_millis(80));
+                }
+                let _ = write!(stdout, "\r\x1b[K");
+                let _ = stdout.flush();
+            }
+        });
+        Self { done, thread: Some(thread) }
+    }
+}
+
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::cli::Format;
+use crate::render::{Renderer, Spinner, Verbosity};
+use crate::task::Status;
+
+
+// outcome of every task run, printed as it happens in the chosen format and
+// summed up at the end
+#[derive(Debug)]
+pub struct Report {
+    format: Format,
+    junit: bool,
+    renderer: Renderer,
+    started: Instant,
+    results: Vec<Outcome>,
+    output: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub task: String,
+    pub name: String,
+    pub kind: &'static str,
+    pub depth: usize,
+    pub dst: Option<PathBuf>,
+    // resolved source and destination or command, for verbose output
+    pub detail: Option<String>,
+    pub su: bool,
+    pub ignored: bool,
+    pub status: Status,
+    // whether the task was only planned by a dry run, and what it would do
+    pub planned: bool,
+    pub action: Option<String>,
+    pub duration: Duration,
+    pub output: Option<String>,
+}
+
+
+impl Report {
+    pub fn new(format: Format, junit: bool, verbosity: Verbosity) -> Self {
+        Self { format, junit, renderer: Renderer::new(verbosity),
+               started: Instant::now(), results: Vec::new(), output: None }
+    }
+
+    // whether shell tasks should hand their output over rather than let it
+    // through to the terminal, which `interactive` ones keep when they can
+    pub fn captures_output(&self, interactive: bool) -> bool {
+        self.junit || !matches!(self.format, Format::Text)
+            || !self.renderer.streams_output(interactive)
+    }
+
+    pub fn spin(&self, name: &str, depth: usize) -> Option<Spinner> {
+        match self.format {
+            Format::Text => self.renderer.spin(name, depth),
+            Format::Json => None,
+        }
+    }
+
+    pub fn set_output(&mut self, output: String) {
+        self.output = Some(output);
+    }
+
+    pub fn start(&mut self, task: &str, name: &str, kind: &str,
+                 depth: usize) {
+        match self.format {
+            Format::Text if kind == "group" =>
+                self.renderer.header(name, depth),
+            Format::Text => (),
+            Format::Json => println!("{}", json!({
+                "event": "start", "task": task, "type": kind,
+            })),
+        }
+    }
+
+    pub fn finish(&mut self, mut outcome: Outcome) {
+        outcome.output = self.output.take();
+        match self.format {
+            Format::Text => self.renderer.finish(&outcome),
+            Format::Json => println!("{}", json!({
+                "event": "finish",
+                "task": outcome.task,
+                "type": outcome.kind,
+                "status": label(&outcome.status),
+                "duration": outcome.duration.as_secs_f64(),
+                "dst": outcome.dst,
+                "error": match &outcome.status {
+                    Status::Failure(reason) => Some(reason),
+                    _ => None,
+                },
+                "action": outcome.action,
+                "su": outcome.su,
+                "ignored": outcome.ignored,
+                "output": outcome.output,
+            })),
+        }
+        self.results.push(outcome);
+    }
+
+    pub fn print(&self) {
+        // groups only aggregate their tasks, which are counted on their own
+        let tasks = self.results.iter()
+                        .filter(|o| o.kind != "group")
+                        .collect::<Vec<_>>();
+        let count = |status| tasks.iter().filter(|o| o.status == status)
+                                  .count();
+        let failures = tasks.iter()
+                            .filter(|o| matches!(o.status, Status::Failure(_)))
+                            .collect::<Vec<_>>();
+        let ignored = failures.iter().filter(|o| o.ignored).count();
+        let elapsed = self.started.elapsed().as_secs_f64();
+
+        if let Format::Json = self.format {
+            println!("{}", json!({
+                "event": "summary",
+                "changed": count(Status::Changed),
+                "unchanged": count(Status::Unchanged),
+                "skipped": count(Status::Skipped),
+                "failed": failures.len(),
+                "ignored": ignored,
+                "duration": elapsed,
+            }));
+            return;
+        }
+
+        let failed = match ignored {
+            0 => failures.len().to_string(),
+            n => format!("{} ({} ignored)", failures.len(), n),
+        };
+        self.renderer.summary(&[
+            ("changed", count(Status::Changed).to_string()),
+            ("unchanged", count(Status::Unchanged).to_string()),
+            ("skipped", count(Status::Skipped).to_string()),
+            ("failed", failed),
+            ("time", format!("{:.2}s", elapsed)),
+        ]);
+        self.renderer.failures(&failures.iter().filter_map(|o| {
+            match &o.status {
+                Status::Failure(reason) =>
+                    Some((o.task.as_str(), reason.as_str(), o.ignored)),
+                _ => None,
+            }
+        }).collect::<Vec<_>>());
+    }
+
+    // junit xml with a test suite for each group holding its own tasks as
+    // test cases
+    pub fn write_junit(&self, path: &Path) -> io::Result<()> {
+        let mut suites = Vec::<(&str, Vec<&Outcome>)>::new();
+        for outcome in self.results.iter().filter(|o| o.kind != "group") {
+            let group = parent(&outcome.task);
+            match suites.iter_mut().find(|(g, _)| *g == group) {
+                Some((_, cases)) => cases.push(outcome),
+                None => suites.push((group, vec![outcome])),
+            }
+        }
+
+        let cases = suites.iter().flat_map(|(_, cases)| cases).copied()
+                          .collect::<Vec<_>>();
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<testsuites name=\"zapp\" {}>\n",
+                              counts(&cases, self.started.elapsed())));
+        for (group, cases) in &suites {
+            let duration = self.results.iter()
+                               .find(|o| o.kind == "group" && o.task == *group)
+                               .map_or(Duration::ZERO, |o| o.duration);
+            let name = if group.is_empty() { "main" } else { group };
+            xml.push_str(&format!("  <testsuite name=\"{}\" {}>\n",
+                                  escape(name), counts(cases, duration)));
+            cases.iter().for_each(|o| xml.push_str(&testcase(name, o)));
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        fs::write(path, xml)
+    }
+}
+
+
+fn testcase(suite: &str, outcome: &Outcome) -> String {
+    let mut xml = format!("    <testcase name=\"{}\" classname=\"{}\" \
+                           time=\"{:.3}\">\n",
+                          escape(&outcome.name), escape(suite),
+                          outcome.duration.as_secs_f64());
+    let output = outcome.output.as_deref().filter(|o| !o.is_empty());
+    match (&outcome.status, outcome.ignored, output) {
+        (Status::Failure(reason), false, output) => xml.push_str(&format!(
+            "      <failure message=\"{}\">{}</failure>\n", escape(reason),
+            escape(&match output {
+                Some(output) => format!("{}\n\n{}", reason, output),
+                None => reason.clone(),
+            }))),
+        (status, ignored, output) => {
+            match (status, ignored) {
+                (Status::Failure(reason), _) => xml.push_str(&format!(
+                    "      <skipped message=\"failure ignored: {}\"/>\n",
+                    escape(reason))),
+                (Status::Skipped, _) => xml.push_str("      <skipped/>\n"),
+                _ => (),
+            }
+            if let Some(output) = output {
+                xml.push_str(&format!("      <system-out>{}</system-out>\n",
+                                      escape(output)));
+            }
+        }
+    }
+    xml.push_str("    </testcase>\n");
+    xml
+}
+
+
+fn counts(cases: &[&Outcome], duration: Duration) -> String {
+    let failures = cases.iter().filter(|o| {
+        matches!(o.status, Status::Failure(_)) && !o.ignored
+    }).count();
+    let skipped = cases.iter().filter(|o| {
+        o.status == Status::Skipped || o.ignored
+    }).count();
+    format!("tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\"",
+            cases.len(), failures, skipped, duration.as_secs_f64())
+}
+
+
+fn parent(task: &str) -> &str {
+    task.rfind('/').map_or("", |i| &task[..i])
+}
+
+
+fn escape(text: &str) -> String {
+    text.chars().map(|c| match c {
+        '&' => "&amp;".to_owned(),
+        '<' => "&lt;".to_owned(),
+        '>' => "&gt;".to_owned(),
+        '"' => "&quot;".to_owned(),
+        c if c.is_control() && c != '\n' && c != '\t' => String::new(),
+        c => c.to_string(),
+    }).collect()
+}
+
+
+fn label(status: &Status) -> &'static str {
+    match status {
+        Status::Changed => "changed",
+        Status::Unchanged => "unchanged",
+        Status::Failure(_) => "failure",
+        Status::Skipped => "skipped",
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use crate::error::{Error, Result};
+
+
+// a yaml file kept in memory so that errors can point back into it
+#[derive(Debug)]
+pub struct Source {
+    path: PathBuf,
+    text: String,
+    marks: Vec<Mark>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Key {
+    Index(usize),
+    Field(String),
+}
+
+// where a node starts, along with its text if it is a scalar or a key
+#[derive(Debug)]
+struct Mark {
+    path: Vec<Key>,
+    line: usize,
+    column: usize,
+    scalar: Option<String>,
+}
+
+#[derive(Default)]
+struct Marks {
+    marks: Vec<Mark>,
+    // open collections, with the index of their own mark
+    stack: Vec<(Vec<Key>, Frame, usize)>,
+}
+
+enum Frame {
+    Sequence(usize),
+    Key,
+    Value(String),
+}
+
+
+impl Source {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Ok(Self::new(path, text))
+    }
+
+    pub fn new(path: &Path, text: String) -> Self {
+        let mut marks = Marks::default();
+        // syntax errors are reported with a location by `parse` instead
+        let _ = Parser::new(text.chars()).load(&mut marks, false);
+        Self { path: path.to_owned(), text, marks: marks.marks }
+    }
+
+    pub fn parse(&self) -> Result<Value> {
+        serde_yaml::from_str(&self.text).map_err(|e| {
+            let message = e.to_string();
+            let message = match message.rfind(" at line ") {
+                Some(end) => message[..end].to_owned(),
+                None => message,
+            };
+            match e.location() {
+                Some(at) => self.error_at(at.line(), at.column(), message),
+                None => Error::Yaml { path: self.path.clone(), source: e },
+            }
+        })
+    }
+
+    // deserialize `value`, found at `at`, pointing errors at the
+    // offending scalar when serde names one
+    pub fn deserialize<T: DeserializeOwned>(&self, at: &[Key], value: &Value)
+        -> Result<T>
+    {
+        serde_yaml::from_value(value.clone()).map_err(|e| {
+            let message = e.to_string();
+            let culprit = unknown(&message).and_then(|(word, _)| {
+                self.marks.iter().find(|m| {
+                    m.path.starts_with(at)
+                        && m.scalar.as_deref() == Some(word)
+                })
+            });
+            match culprit {
+                Some(m) => self.error_at(m.line, m.column, message),
+                None => self.error(at, message),
+            }
+        })
+    }
+
+    // an error about the node at `at`, or its closest ancestor that
+    // actually appears in the file
+    pub fn error(&self, at: &[Key], message: impl Into<String>) -> Error {
+        let mark = (0..=at.len()).rev().find_map(|n| {
+            self.marks.iter().find(|m| m.path == at[..n])
+        });
+        match mark {
+            Some(m) => self.error_at(m.line, m.column, message),
+            None => self.error_at(1, 1, message),
+        }
+    }
+
+    fn error_at(&self, line: usize, column: usize,
+                message: impl Into<String>) -> Error {
+        let message = message.into();
+        let hint = unknown(&message).and_then(|(word, expected)| {
+            suggest(word, &expected)
+        }).map(|s| format!("did you mean `{}`?", s));
+
+        Error::Located {
+            file: self.path.clone(), line, column,
+            snippet: self.text.lines().nth(line - 1)
+                                      .unwrap_or_default().to_owned(),
+            message, hint,
+        }
+    }
+}
+
+
+impl Marks {
+    // work out the path of a node that is starting, recording where it is
+    fn enter(&mut self, mark: Marker, scalar: Option<&str>) -> Vec<Key> {
+        let path = match self.stack.last_mut() {
+            None => Vec::new(),
+            Some((parent, Frame::Sequence(i), _)) => {
+                *i += 1;
+                child(parent, Key::Index(*i - 1))
+            }
+            Some((parent, frame @ Frame::Key, _)) => {
+                let key = scalar.unwrap_or_default().to_owned();
+                *frame = Frame::Value(key.clone());
+                child(parent, Key::Field(key))
+            }
+            Some((parent, frame, _)) => {
+                // values share their key's path, whose mark comes first
+                match std::mem::replace(frame, Frame::Key) {
+                    Frame::Value(key) => child(parent, Key::Field(key)),
+                    _ => unreachable!(),
+                }
+            }
+        };
+
+        let (line, column) = (mark.line(), mark.col() + 1);
+        // block collections are only marked once their first key is known
+        for &(_, _, i) in &self.stack {
+            let m = &mut self.marks[i];
+            if (line, column) < (m.line, m.column) {
+                m.line = line;
+                m.column = column;
+            }
+        }
+        self.marks.push(Mark { path: path.clone(), line, column,
+                               scalar: scalar.map(str::to_owned) });
+        path
+    }
+}
+
+
+impl MarkedEventReceiver for Marks {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(text, ..) => { self.enter(mark, Some(&text)); }
+            Event::Alias(_) => { self.enter(mark, None); }
+            Event::SequenceStart(_) => {
+                let path = self.enter(mark, None);
+                self.stack.push((path, Frame::Sequence(0),
+                                 self.marks.len() - 1));
+            }
+            Event::MappingStart(_) => {
+                let path = self.enter(mark, None);
+                self.stack.push((path, Frame::Key, self.marks.len() - 1));
+            }
+            Event::SequenceEnd | Event::MappingEnd => { self.stack.pop(); }
+            _ => (),
+        }
+    }
+}
+
+
+fn child(parent: &[Key], key: Key) -> Vec<Key> {
+    let mut path = parent.to_vec();
+    path.push(key);
+    path
+}
+
+
+// the offending word and the accepted ones of serde's
+// "unknown variant/field `x`, expected ..." messages
+fn unknown(message: &str) -> Option<(&str, Vec<&str>)> {
+    if !message.starts_with("unknown ") {
+        return None;
+    }
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let word = quoted.next()?;
+    Some((word, quoted.collect()))
+}
+
+
+// the closest of `candidates` to `word`, if any is close enough
+pub fn suggest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+              .map(|c| (strsim::levenshtein(word, c), *c))
+              .filter(|(distance, _)| *distance <= word.len() / 3 + 1)
+              .min()
+              .map(|(_, c)| c)
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    // the --root the files were deployed under, if any
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    root: Option<PathBuf>,
+    #[serde(default)] pub files: BTreeMap<PathBuf, Entry>,
+    #[serde(skip)] dir: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Entry {
+    pub task: String,
+    #[serde(default, skip_serializing_if="is_false")] pub su: bool,
+    #[serde(flatten)] pub managed: Managed,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag="type", rename_all="lowercase")]
+pub enum Managed {
+    File { hash: String, #[serde(with="octal")] mode: u32 },
+    Symlink { target: PathBuf },
+}
+
+
+impl Ledger {
+    // refuses a ledger kept for another root, whose paths would all look
+    // undeclared and get pruned
+    pub fn load(dir: &Path, root: Option<&Path>) -> Result<Self> {
+        let path = path(dir);
+        let ledger = match fs::read_to_string(&path) {
+            Ok(s) => serde_yaml::from_str::<Self>(&s).map_err(|e| {
+                Error::Yaml { path: path.clone(), source: e }
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound =>
+                Self { root: root.map(Path::to_owned), ..Self::default() },
+            Err(e) => return Err(Error::io(path, e)),
+        };
+        if ledger.root.as_deref() != root {
+            return Err(Error::ForeignLedger(path));
+        }
+        Ok(Self { dir: dir.to_owned(), ..ledger })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = path(&self.dir);
+        let yaml = serde_yaml::to_string(self).map_err(|e| {
+            Error::Yaml { path: path.clone(), source: e }
+        })?;
+        fs::create_dir_all(&self.dir)
+           .and_then(|_| fs::write(&path, yaml))
+           .map_err(|e| Error::io(path, e))
+    }
+
+    pub fn record(&mut self, path: &Path, task: String, su: bool,
+                  managed: Managed) {
+        if let Ok(path) = std::path::absolute(path) {
+            self.files.insert(path, Entry { task, su, managed });
+        }
+    }
+}
+
+
+pub fn hash(contents: &[u8]) -> String {
+    Sha256::digest(contents).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+// whether `path` is still exactly what zapp last deployed there
+pub fn untouched(path: &Path, managed: &Managed) -> bool {
+    match managed {
+        Managed::File { hash: expected, .. } => match fs::read(path) {
+            Ok(contents) => hash(&contents) == *expected,
+            Err(_) => false,
+        },
+        Managed::Symlink { target } => match fs::read_link(path) {
+            Ok(current) => current == *target,
+            Err(_) => false,
+        },
+    }
+}
+
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+
+fn path(dir: &Path) -> PathBuf {
+    dir.join("state.yaml")
+}
+
+
+mod octal {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(mode: &u32, serializer: S)
+        -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_str(&format!("{:o}", mode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+        -> Result<u32, D::Error>
+    {
+        let mode = String::deserialize(deserializer)?;
+        u32::from_str_radix(&mode, 8).map_err(D::Error::custom)
+    }
+}
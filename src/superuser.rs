@@ -0,0 +1,128 @@
+use std::cell::Cell;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum Method {
+    #[default] Sudo,
+    Doas,
+}
+
+#[derive(Debug)]
+pub struct Superuser {
+    method: Method,
+    authenticated: Cell<Option<Result<(), &'static str>>>,
+}
+
+
+impl Method {
+    pub fn program(self) -> &'static str {
+        match self {
+            Self::Sudo => "sudo",
+            Self::Doas => "doas",
+        }
+    }
+
+    pub fn from_program(program: &str) -> Option<Self> {
+        [Self::Sudo, Self::Doas].iter().copied()
+                                .find(|m| m.program() == program)
+    }
+}
+
+
+impl Superuser {
+    pub fn new(method: Method) -> Self {
+        Self { method, authenticated: Cell::new(None) }
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    // prompt for credentials once per run, and again only once the cached
+    // ones have expired, say during a long shell task
+    fn authenticate(&self) -> io::Result<()> {
+        let authenticated = match self.authenticated.get() {
+            Some(Ok(())) if self.refresh()? => Ok(()),
+            Some(Err(message)) => Err(message),
+            Some(Ok(())) | None => {
+                let authenticated = self.prompt()?;
+                self.authenticated.set(Some(authenticated));
+                authenticated
+            }
+        };
+        authenticated.map_err(|message| {
+            io::Error::new(io::ErrorKind::PermissionDenied, message)
+        })
+    }
+
+    fn prompt(&self) -> io::Result<Result<(), &'static str>> {
+        let program = self.method.program();
+        let prompted = Command::new(program).arg(match self.method {
+            Method::Sudo => "-v",
+            Method::Doas => "true",
+        }).status()?;
+        if !prompted.success() {
+            return Ok(Err("superuser authentication failed"));
+        }
+
+        // without cached credentials every privileged command would prompt
+        // all over again, as doas does unless its rule has `persist`
+        Ok(match (self.refresh()?, self.method) {
+            (true, _) => Ok(()),
+            (false, Method::Sudo) => Err("sudo does not cache credentials, \
+                                          check timestamp_timeout in sudoers"),
+            (false, Method::Doas) => Err("doas does not keep credentials, \
+                                          add `persist` to its rule in \
+                                          doas.conf"),
+        })
+    }
+
+    // whether the cached credentials are still good, extending them if so
+    fn refresh(&self) -> io::Result<bool> {
+        let mut cmd = Command::new(self.method.program());
+        match self.method {
+            Method::Sudo => cmd.args(["-n", "-v"]),
+            Method::Doas => cmd.args(["-n", "true"]),
+        };
+        Ok(cmd.stdin(Stdio::null()).status()?.success())
+    }
+
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> io::Result<Command> {
+        self.authenticate()?;
+        let mut cmd = Command::new(self.method.program());
+        // reuse the cached credentials instead of prompting again
+        cmd.arg("-n").arg(program);
+        Ok(cmd)
+    }
+
+    pub fn run<I, S>(&self, args: I, input: Option<&[u8]>) -> io::Result<()>
+    where
+        I: IntoIterator<Item=S>,
+        S: AsRef<OsStr>,
+    {
+        let mut args = args.into_iter();
+        let program = args.next().expect("missing superuser command");
+        let mut cmd = self.command(program)?;
+        cmd.args(args);
+
+        let status = match input {
+            None => cmd.status()?,
+            Some(input) => {
+                let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+                child.stdin.take().unwrap().write_all(input)?;
+                child.wait()?
+            }
+        };
+
+        if status.success() { Ok(()) } else {
+            Err(io::Error::other(format!("superuser command failed: {}",
+                                         status)))
+        }
+    }
+}
@@ -1,45 +1,82 @@
 use std::fmt;
-use std::fs::{self, File};
-use std::os::unix::fs as unixfs;
-use std::process::Command;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
 
 use serde::Deserialize;
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 
-use crate::config::{self, Params};
-use crate::filesystem;
+use crate::config::{ConfigDir, Params};
+use crate::diff;
+use crate::error::{Error, Result};
+use crate::filesystem::{self, FileState, LinkState};
+use crate::plan::{Action, Drift};
+use crate::report::Outcome;
+use crate::source::{Key, Source};
+use crate::state::{self, Managed};
+use crate::superuser::Superuser;
 
 
-pub trait Runnable {
-    fn run(&self, params: &mut Params) -> Status;
+const TASK_FIELDS: [&str; 3] = ["name", "su", "ignore_errors"];
+const GROUP_FIELDS: [&str; 2] = ["ignore_errors", "on_failure"];
+const TASK_TYPES: [&str; 4] = ["copy", "symlink", "template", "shell"];
+
+
+trait Runnable {
+    fn run(&self, params: &mut Params) -> Result<Status>;
+}
+
+trait Plannable {
+    fn plan(&self, params: &Params) -> Result<Action>;
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Status {
-    Success,
-    Failure,
+    Changed,
+    Unchanged,
+    Failure(String),
     Skipped,
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Task {
     #[serde(default)] name: String,
     #[serde(rename="su", default)] as_superuser: bool,
+    #[serde(default)] ignore_errors: bool,
     #[serde(flatten)] variant: TaskType,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 enum TaskType {
     Unknown,
-    Group(Vec<Task>),
+    #[serde(skip)] Group(Vec<Task>, OnFailure),
     #[serde(rename="copy")] Copy(CopyTask),
     #[serde(rename="symlink")] Symlink(SymlinkTask),
     #[serde(rename="template")] Template(TemplateTask),
     #[serde(rename="shell")] Shell(ShellTask),
 }
 
-#[derive(Debug, Deserialize)]
+// whether a group keeps running its remaining tasks after one fails
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all="lowercase")]
+enum OnFailure {
+    #[default] Abort,
+    Continue,
+}
+
+#[derive(Default, Deserialize)]
+struct GroupOptions {
+    #[serde(default)] ignore_errors: bool,
+    // taken from the enclosing group when not given
+    #[serde(default)] on_failure: Option<OnFailure>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 struct CopyTask {
     src: String,
     dst: String,
@@ -48,13 +85,22 @@ struct CopyTask {
     mode: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 struct SymlinkTask {
     src: String,
     dst: String,
+    #[serde(default)] on_conflict: Conflict,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all="lowercase")]
+enum Conflict {
+    #[default] Fail,
+    Backup,
+    Replace,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 struct TemplateTask {
     src: String,
     dst: String,
@@ -63,68 +109,453 @@ struct TemplateTask {
     mode: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 struct ShellTask(String);
 
 
 impl Task {
     fn new(name: &str, variant: TaskType) -> Self {
-        Self { name: name.to_owned(), as_superuser: false, variant }
+        Self { name: name.to_owned(), as_superuser: false,
+               ignore_errors: false, variant }
     }
 
-    fn group(name: &str, tasks: Vec<Task>) -> Self {
-        Self::new(name, TaskType::Group(tasks))
+    fn group(name: &str, tasks: Vec<Task>, on_failure: OnFailure) -> Self {
+        Self::new(name, TaskType::Group(tasks, on_failure))
     }
 
-    pub fn parse_from_config(task_name: &str, config: &Value) -> Self {
-        let tasks = config.as_sequence().unwrap().iter().map(|t| match t {
-            Value::String(s) => Self::load_from_file(s),
-            Value::Mapping(m) => {
-                let (k, v) = m.iter().next().unwrap();
-                Self::parse_from_config(k.as_str().unwrap(), v)
+    pub fn parse_from_config(dir: &ConfigDir, source: &Source,
+                             config: &Value, strict: bool) -> Result<Self> {
+        let at = [Key::Field("tasks".to_owned())];
+        let on_failure = OnFailure::default();
+        let tasks = Self::parse_tasks(dir, "", &at, source, config,
+                                      on_failure, strict)?;
+        Ok(Self::group("main", tasks, on_failure))
+    }
+
+    // the groups of task files follow the `on_failure` of the group they are
+    // listed in
+    fn parse_tasks(dir: &ConfigDir, task_path: &str, at: &[Key],
+                   source: &Source, config: &Value, on_failure: OnFailure,
+                   strict: bool)
+        -> Result<Vec<Self>>
+    {
+        let entries = config.as_sequence().ok_or_else(|| {
+            source.error(at, "expected a list of tasks")
+        })?;
+
+        entries.iter().enumerate().map(|(i, t)| {
+            let at = [at, &[Key::Index(i)]].concat();
+            match t {
+                Value::String(s) =>
+                    Self::load_from_file(dir, s, &join(task_path, s),
+                                         on_failure, strict),
+                Value::Mapping(m) =>
+                    Self::parse_group(dir, task_path, &at, source, m,
+                                      on_failure, strict),
+                _ if strict => Err(source.error(&at,
+                    "expected a task file name or a task group")),
+                _ => Ok(Self::new("unknown", TaskType::Unknown)),
+            }
+        }).collect()
+    }
+
+    // a `name: [tasks]` mapping, optionally alongside the group's options
+    fn parse_group(dir: &ConfigDir, task_path: &str, at: &[Key],
+                   source: &Source, group: &Mapping, on_failure: OnFailure,
+                   strict: bool)
+        -> Result<Self>
+    {
+        let (options, names) = group.iter().partition::<Vec<_>, _>(|(k, _)| {
+            k.as_str().is_some_and(|k| GROUP_FIELDS.contains(&k))
+        });
+
+        let (k, v) = names.first().ok_or_else(|| {
+            source.error(at, "empty task group")
+        })?;
+        let k = k.as_str().ok_or_else(|| {
+            source.error(at, "task group names must be strings")
+        })?;
+        if let (true, Some((extra, _))) = (strict, names.get(1)) {
+            let extra = extra.as_str().unwrap_or_default();
+            return Err(source.error(
+                &[at, &[Key::Field(extra.to_owned())]].concat(),
+                format!("task group `{}` has a second name `{}`, \
+                         use a separate list entry", k, extra)));
+        }
+
+        let options = options.into_iter()
+                             .map(|(k, v)| (k.clone(), v.clone()))
+                             .collect();
+        let options = source.deserialize::<GroupOptions>(
+            at, &Value::Mapping(options))?;
+
+        let on_failure = options.on_failure.unwrap_or(on_failure);
+        let at = [at, &[Key::Field(k.to_owned())]].concat();
+        let tasks = Self::parse_tasks(dir, &join(task_path, k), &at, source,
+                                      v, on_failure, strict)?;
+        Ok(Self { ignore_errors: options.ignore_errors,
+                  ..Self::group(k, tasks, on_failure) })
+    }
+
+    pub fn select(&self, selection: &[String]) -> Result<Self> {
+        if selection.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let paths = selection.iter()
+                             .map(|p| p.split('/').filter(|s| !s.is_empty())
+                                       .collect::<Vec<_>>())
+                             .collect::<Vec<_>>();
+
+        if let Some((_, name)) = paths.iter().zip(selection)
+                                      .find(|(p, _)| self.find(p).is_none()) {
+            return Err(Error::UnknownTask(name.clone()));
+        }
+
+        let paths = paths.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        Ok(self.prune(&paths))
+    }
+
+    fn find(&self, path: &[&str]) -> Option<&Self> {
+        match path.split_first() {
+            None => Some(self),
+            Some((name, rest)) => match &self.variant {
+                TaskType::Group(tasks, _) => tasks.iter()
+                                               .find(|t| t.name == *name)
+                                               .and_then(|t| t.find(rest)),
+                _ => None,
             },
-            _ => Self::new("unknown", TaskType::Unknown),
-        }).collect();
+        }
+    }
+
+    // keep only the selected subtrees along with their ancestors
+    fn prune(&self, paths: &[&[&str]]) -> Self {
+        let (tasks, on_failure) = match &self.variant {
+            TaskType::Group(tasks, on_failure)
+                    if !paths.iter().any(|p| p.is_empty()) => {
+                let tasks = tasks.iter().filter_map(|t| {
+                    let paths = paths.iter()
+                                     .filter(|p| p[0] == t.name)
+                                     .map(|p| &p[1..])
+                                     .collect::<Vec<_>>();
+                    if paths.is_empty() { None } else { Some(t.prune(&paths)) }
+                }).collect();
+                (tasks, *on_failure)
+            }
+            _ => return self.clone(),
+        };
 
-        Self::group(task_name, tasks)
+        Self { variant: TaskType::Group(tasks, on_failure), ..self.clone() }
     }
 
-    fn load_from_file(task_name: &str) -> Self {
-        let task_file = File::open(config::asset("tasks", &format!("{}.yaml",
-                                                                   task_name)))
-                             .expect("unable to open task file");
-        let tasks = serde_yaml::from_reader(task_file)
-                               .expect("unable to parse task file");
+    pub fn list(&self, depth: usize) {
+        println!("{: <1$}{name}{kind}", "", depth * 2, name=self.name,
+                 kind=match &self.variant {
+                     TaskType::Group(..) => String::new(),
+                     variant => format!(" [{}]", variant.kind()),
+                 });
+        if let TaskType::Group(tasks, _) = &self.variant {
+            tasks.iter().for_each(|t| t.list(depth + 1));
+        }
+    }
 
-        Self::group(task_name, tasks)
+    // every destination the task tree deploys files or links to
+    pub fn destinations(&self, params: &Params) -> Vec<PathBuf> {
+        match &self.variant {
+            TaskType::Group(tasks, _) =>
+                tasks.iter().flat_map(|t| t.destinations(params)).collect(),
+            variant => variant.destination(params).into_iter().collect(),
+        }
+    }
+
+    // report how each deployed destination differs from what would be
+    // deployed right now, returning whether everything is in sync
+    pub fn status(&self, params: &mut Params) -> bool {
+        params.path.push(self.name.clone());
+        let in_sync = match &self.variant {
+            TaskType::Group(tasks, _) => {
+                let mut in_sync = true;
+                for task in tasks {
+                    in_sync &= task.status(params);
+                }
+                in_sync
+            }
+            variant => match variant.drift(params) {
+                None => true,
+                Some(Ok((dst, drift))) => {
+                    println!("{:<12} {} ({})", drift, dst.display(),
+                             params.task_path());
+                    drift == Drift::InSync
+                }
+                Some(Err(e)) => {
+                    let e = e.in_task(params.task_path());
+                    println!("{:<12} {}", "error", e);
+                    false
+                }
+            },
+        };
+        params.path.pop();
+        in_sync
+    }
+
+    // check that every source exists and every template renders with the
+    // current params, without touching any destination
+    pub fn validate(&self, params: &mut Params) -> Vec<Error> {
+        params.path.push(self.name.clone());
+        let problems = match &self.variant {
+            TaskType::Group(tasks, _) =>
+                tasks.iter().flat_map(|t| t.validate(params)).collect(),
+            variant => variant.validate(params).err()
+                              .map(|e| e.in_task(params.task_path()))
+                              .into_iter().collect(),
+        };
+        params.path.pop();
+        problems
+    }
+
+    pub fn count(&self) -> usize {
+        match &self.variant {
+            TaskType::Group(tasks, _) => tasks.iter().map(Self::count).sum(),
+            _ => 1,
+        }
     }
-}
 
+    fn load_from_file(dir: &ConfigDir, task_name: &str, task_path: &str,
+                      on_failure: OnFailure, strict: bool) -> Result<Self> {
+        let path = dir.asset("tasks", &format!("{}.yaml", task_name));
+        Self::parse_file(&path, strict)
+             .map(|tasks| Self::group(task_name, tasks, on_failure))
+             .map_err(|e| e.in_task(task_path.to_owned()))
+    }
 
-impl Runnable for Task {
-    fn run(&self, params: &mut Params) -> Status {
-        // TODO: handle as_superuser == true
-        let status = if !self.as_superuser {
-            self.variant.run(params)
-        } else { Status::Skipped };
-        println!("{: <1$}{name}: {status}", "", params.depth * 2,
-                 name=self.name, status=status);
+    fn parse_file(path: &Path, strict: bool) -> Result<Vec<Self>> {
+        let source = Source::read(path)?;
+        let tasks = source.parse()?;
+        let entries = tasks.as_sequence().ok_or_else(|| {
+            source.error(&[], "expected a list of tasks")
+        })?;
+
+        entries.iter().enumerate().map(|(i, entry)| {
+            Self::parse_entry(&source, &[Key::Index(i)], entry, strict)
+        }).collect()
+    }
+
+    // check which type of task `entry` is before handing it to serde, whose
+    // own errors for flattened enums don't say much
+    fn parse_entry(source: &Source, at: &[Key], entry: &Value, strict: bool)
+        -> Result<Self>
+    {
+        let mapping = entry.as_mapping().ok_or_else(|| {
+            source.error(at, "expected a task")
+        })?;
+        let field = |path: &[Key], k: &str| {
+            [path, &[Key::Field(k.to_owned())]].concat()
+        };
+        let (kinds, extra) = mapping.iter()
+                                    .filter_map(|(k, _)| k.as_str())
+                                    .filter(|k| !TASK_FIELDS.contains(k))
+                                    .partition::<Vec<_>, _>(|k| {
+                                        TASK_TYPES.contains(k)
+                                    });
+
+        let kind = match (&kinds[..], &extra[..]) {
+            ([kind], []) => *kind,
+            ([kind], [extra, ..]) if strict =>
+                return Err(source.error(&field(at, extra), format!(
+                    "unknown field `{}`, expected one of {}, `{}`",
+                    extra, quoted(&TASK_FIELDS), kind))),
+            ([kind], _) => *kind,
+            ([], [kind, ..]) => return Err(source.error(&field(at, kind),
+                format!("unknown task type `{}`, expected one of {}",
+                        kind, quoted(&TASK_TYPES)))),
+            ([], []) => return Err(source.error(at, format!(
+                "task has no type, expected one of {}", quoted(&TASK_TYPES)))),
+            ([first, second, ..], _) => return Err(source.error(
+                &field(at, second),
+                format!("task has more than one type: `{}` and `{}`",
+                        first, second))),
+        };
+
+        let at = field(at, kind);
+        if let (true, Some(body)) = (strict, entry[kind].as_mapping()) {
+            let known = task_fields(kind);
+            let unknown = body.iter().filter_map(|(k, _)| k.as_str())
+                              .find(|k| !known.contains(k));
+            if let Some(k) = unknown {
+                return Err(source.error(&field(&at, k), format!(
+                    "unknown field `{}`, expected one of {}",
+                    k, quoted(known))));
+            }
+        }
+
+        source.deserialize(&at, entry)
+    }
+
+    pub fn run(&self, params: &mut Params) -> Status {
+        let elevated = params.elevated;
+        params.elevated |= self.as_superuser;
+        params.path.push(self.name.clone());
+        let started = Instant::now();
+        params.report.start(&params.task_path(), &self.name,
+                            self.variant.kind(), params.depth());
+
+        let plan = if params.dry_run {
+            self.variant.plan(params)
+        } else { None };
+        let planned = plan.is_some();
+        let (status, action) = match plan {
+            Some(Ok(action)) => (Status::Skipped, Some(action.to_string())),
+            Some(Err(e)) => (Status::Failure(e.to_string()), None),
+            None => (self.variant.run(params).unwrap_or_else(|e| {
+                Status::Failure(e.to_string())
+            }), None),
+        };
+
+        params.report.finish(Outcome {
+            task: params.task_path(), name: self.name.clone(),
+            kind: self.variant.kind(), depth: params.depth(),
+            dst: self.variant.destination(params),
+            detail: self.variant.detail(params), su: params.elevated,
+            ignored: self.ignore_errors, status: status.clone(), planned,
+            action, duration: started.elapsed(), output: None,
+        });
+        params.path.pop();
+        params.elevated = elevated;
         status
     }
 }
 
 
+impl TaskType {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Group(..) => "group",
+            Self::Copy(_) => "copy",
+            Self::Symlink(_) => "symlink",
+            Self::Template(_) => "template",
+            Self::Shell(_) => "shell",
+        }
+    }
+
+    fn destination(&self, params: &Params) -> Option<PathBuf> {
+        let dst = match self {
+            Self::Copy(CopyTask { dst, .. })
+                | Self::Symlink(SymlinkTask { dst, .. })
+                | Self::Template(TemplateTask { dst, .. }) => dst,
+            Self::Unknown | Self::Group(..) | Self::Shell(_) => return None,
+        };
+        std::path::absolute(params.target.path(dst)).ok()
+    }
+
+    fn detail(&self, params: &Params) -> Option<String> {
+        let (dir, src, dst) = match self {
+            Self::Unknown | Self::Group(..) => return None,
+            Self::Shell(ShellTask(command)) =>
+                return Some(format!("$ {}", command)),
+            Self::Copy(CopyTask { src, dst, .. })
+                | Self::Symlink(SymlinkTask { src, dst, .. }) =>
+                ("files", src, dst),
+            Self::Template(TemplateTask { src, dst, .. }) =>
+                ("templates", src, dst),
+        };
+        Some(format!("{} -> {}", params.asset(dir, src).display(),
+                     params.target.path(dst).display()))
+    }
+
+    fn drift(&self, params: &Params) -> Option<Result<(PathBuf, Drift)>> {
+        match self {
+            Self::Unknown | Self::Group(..) | Self::Shell(_) => None,
+            Self::Copy(task) => Some(task.drift(params)),
+            Self::Symlink(task) => Some(task.drift(params)),
+            Self::Template(task) => Some(task.drift(params)),
+        }
+    }
+
+    fn validate(&self, params: &Params) -> Result<()> {
+        match self {
+            Self::Unknown | Self::Group(..) | Self::Shell(_) => Ok(()),
+            Self::Copy(CopyTask { src, .. })
+                | Self::Symlink(SymlinkTask { src, .. }) => {
+                let src = params.asset("files", src);
+                src.metadata().map(|_| ()).map_err(|e| Error::io(&src, e))
+            }
+            Self::Template(task) => task.render(params).map(|_| ()),
+        }
+    }
+
+    fn plan(&self, params: &Params) -> Option<Result<Action>> {
+        match self {
+            Self::Unknown | Self::Group(..) => None,
+            Self::Copy(task) => Some(task.plan(params)),
+            Self::Symlink(task) => Some(task.plan(params)),
+            Self::Template(task) => Some(task.plan(params)),
+            Self::Shell(task) => Some(task.plan(params)),
+        }
+    }
+}
+
+
+impl CopyTask {
+    fn drift(&self, params: &Params) -> Result<(PathBuf, Drift)> {
+        let src = params.asset("files", &self.src);
+        let contents = fs::read(&src).map_err(|e| Error::io(&src, e))?;
+        let dst = params.target.path(&self.dst);
+        let drift = filesystem::compare(&dst, &contents, self.mode).into();
+        Ok((dst, drift))
+    }
+}
+
+
+impl SymlinkTask {
+    fn drift(&self, params: &Params) -> Result<(PathBuf, Drift)> {
+        let src = params.asset("files", &self.src);
+        let dst = params.target.path(&self.dst);
+        let drift = filesystem::compare_link(&src, &dst).into();
+        Ok((dst, drift))
+    }
+}
+
+
+impl TemplateTask {
+    fn drift(&self, params: &Params) -> Result<(PathBuf, Drift)> {
+        let text = self.render(params)?;
+        let dst = params.target.path(&self.dst);
+        let drift = filesystem::compare(&dst, text.as_bytes(), self.mode)
+                              .into();
+        Ok((dst, drift))
+    }
+}
+
+
 impl Runnable for TaskType {
-    fn run(&self, params: &mut Params) -> Status {
+    fn run(&self, params: &mut Params) -> Result<Status> {
         match self {
-            Self::Unknown => Status::Skipped,
-            Self::Group(tasks) => {
-                params.depth += 1;
-                let status = tasks.iter().map(|t| t.run(params))
-                                  .find(|&s| s == Status::Failure)
-                                  .unwrap_or(Status::Success);
-                params.depth -= 1;
-                status
+            Self::Unknown => Ok(Status::Skipped),
+            Self::Group(tasks, on_failure) => {
+                let mut status = Status::Skipped;
+                let mut failed = 0;
+                for task in tasks {
+                    match task.run(params) {
+                        Status::Failure(_) if task.ignore_errors => (),
+                        Status::Failure(_) => {
+                            failed += 1;
+                            if *on_failure == OnFailure::Abort
+                                    && !params.keep_going {
+                                break;
+                            }
+                        }
+                        Status::Changed => status = Status::Changed,
+                        Status::Unchanged if status == Status::Skipped =>
+                            status = Status::Unchanged,
+                        Status::Unchanged | Status::Skipped => (),
+                    }
+                }
+                Ok(match failed {
+                    0 => status,
+                    1 => Status::Failure("1 task failed".to_owned()),
+                    n => Status::Failure(format!("{} tasks failed", n)),
+                })
             }
             Self::Copy(task) => task.run(params),
             Self::Symlink(task) => task.run(params),
@@ -136,76 +567,251 @@ impl Runnable for TaskType {
 
 
 impl Runnable for CopyTask {
-    fn run(&self, _: &mut Params) -> Status {
-        let src = config::asset("files", &self.src);
-        let dst = filesystem::expand_path(&self.dst);
-        filesystem::create_valid_parent(&dst);
-
-        match fs::copy(src, &dst) {
-            Err(_) => return Status::Failure,
-            _ => (),
-        }
-
-        match filesystem::set_permissions(dst, self.mode) {
-            Ok(_) => Status::Success,
-            _ => Status::Failure,
-        }
+    fn run(&self, params: &mut Params) -> Result<Status> {
+        let src = params.asset("files", &self.src);
+        let dst = params.target.path(&self.dst);
+        let contents = fs::read(&src).map_err(|e| Error::io(&src, e))?;
+
+        let status = deploy(params, &dst, &contents, self.mode,
+                            |su| filesystem::copy(&src, &dst, su))?;
+        Ok(record_file(params, status, &dst, &contents))
     }
 }
 
 
 impl Runnable for SymlinkTask {
-    fn run(&self, _: &mut Params) -> Status {
-        let src = config::asset("files", &self.src);
-        let dst = filesystem::expand_path(&self.dst);
-        filesystem::create_valid_parent(&dst);
-
-        match unixfs::symlink(src, dst) {
-            Ok(_) => Status::Success,
-            _ => Status::Failure,
-        }
+    fn run(&self, params: &mut Params) -> Result<Status> {
+        let su = params.superuser();
+        let src = params.asset("files", &self.src);
+        let dst = params.target.path(&self.dst);
+
+        let cleared = match (filesystem::compare_link(&src, &dst),
+                             self.on_conflict) {
+            (LinkState::Linked, _) =>
+                return Ok(record_link(params, Status::Unchanged, &dst, src)),
+            (LinkState::Occupied, Conflict::Fail) => return Err(occupied(&dst)),
+            (LinkState::Missing, _) =>
+                filesystem::create_valid_parent(&dst, su),
+            (LinkState::Stale, _) | (LinkState::Occupied, Conflict::Backup) =>
+                params.backups.save(&dst, su)
+                              .and_then(|_| filesystem::remove(&dst, su)),
+            (LinkState::Occupied, Conflict::Replace) =>
+                filesystem::remove(&dst, su),
+        };
+
+        cleared.and_then(|_| filesystem::symlink(&src, &dst, su))
+               .map_err(|e| Error::io(&dst, e))?;
+        Ok(record_link(params, Status::Changed, &dst, src))
+    }
+}
+
+
+impl TemplateTask {
+    fn render(&self, params: &Params) -> Result<String> {
+        params.templates.render(&self.src, &params.context).map_err(|e| {
+            Error::Template { name: self.src.clone(), source: e }
+        })
     }
 }
 
 
 impl Runnable for TemplateTask {
-    fn run(&self, params: &mut Params) -> Status {
-        let text = match config::TEMPLATES.render(&self.src, &params.context) {
-            Ok(s) => s,
-            _ => return Status::Failure,
+    fn run(&self, params: &mut Params) -> Result<Status> {
+        let text = self.render(params)?;
+        let dst = params.target.path(&self.dst);
+
+        let status = deploy(params, &dst, text.as_bytes(), self.mode,
+                            |su| filesystem::write(&dst, text.as_bytes(), su))?;
+        Ok(record_file(params, status, &dst, text.as_bytes()))
+    }
+}
+
+
+impl Runnable for ShellTask {
+    fn run(&self, params: &mut Params) -> Result<Status> {
+        let shell = "/usr/bin/sh";
+        let mut cmd = match params.superuser() {
+            None => Command::new(shell),
+            Some(su) => su.command(shell).map_err(|e| Error::io(shell, e))?,
+        };
+        cmd.args(["-c", &self.0]);
+        // superuser commands are the likely ones to ask for input
+        let interactive = params.superuser().is_some();
+        let exit_status = if params.report.captures_output(interactive) {
+            let name = params.path.last().map_or("", String::as_str);
+            let spinner = params.report.spin(name, params.depth());
+            let output = cmd.stdin(Stdio::inherit()).output()
+                            .map_err(|e| Error::io(shell, e));
+            drop(spinner);
+            let output = output?;
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            params.report.set_output(text);
+            output.status
+        } else {
+            cmd.status().map_err(|e| Error::io(shell, e))?
         };
-        let dst = filesystem::expand_path(&self.dst);
-        filesystem::create_valid_parent(&dst);
+        Ok(match (exit_status.code(), exit_status.signal()) {
+            (Some(0), _) => Status::Changed,
+            (Some(code), _) => Status::Failure(format!("exit code {}", code)),
+            (None, Some(signal)) =>
+                Status::Failure(format!("killed by signal {}", signal)),
+            (None, None) => Status::Failure(exit_status.to_string()),
+        })
+    }
+}
 
-        match fs::write(&dst, text) {
-            Err(_) => return Status::Failure,
-            _ => (),
-        }
 
-        match filesystem::set_permissions(dst, self.mode) {
-            Ok(_) => Status::Success,
-            _ => Status::Failure,
-        }
+impl Plannable for CopyTask {
+    fn plan(&self, params: &Params) -> Result<Action> {
+        let src = params.asset("files", &self.src);
+        let contents = fs::read(&src).map_err(|e| Error::io(&src, e))?;
+        let dst = params.target.path(&self.dst);
+        Ok(write_action(params, dst, &contents, self.mode))
     }
 }
 
 
-impl Runnable for ShellTask {
-    fn run(&self, _params: &mut Params) -> Status {
-        let exit_code = Command::new("/usr/bin/sh")
-                                .args(&["-c", &self.0])
-                                .status()
-                                .expect("failed to run shell command");
-        if exit_code.success() { Status::Success } else { Status::Failure }
+impl Plannable for SymlinkTask {
+    fn plan(&self, params: &Params) -> Result<Action> {
+        let src = params.asset("files", &self.src);
+        let dst = params.target.path(&self.dst);
+
+        Ok(match (filesystem::compare_link(&src, &dst), self.on_conflict) {
+            (LinkState::Linked, _) => Action::Unchanged(dst),
+            (LinkState::Missing, _) => Action::Link { src, dst },
+            (LinkState::Stale, _) => Action::Relink { src, dst },
+            (LinkState::Occupied, Conflict::Fail) => return Err(occupied(&dst)),
+            (LinkState::Occupied, Conflict::Backup) => Action::Replace {
+                backup: Some(params.backups.path_for(&dst)), src, dst,
+            },
+            (LinkState::Occupied, Conflict::Replace) =>
+                Action::Replace { backup: None, src, dst },
+        })
+    }
+}
+
+
+impl Plannable for TemplateTask {
+    fn plan(&self, params: &Params) -> Result<Action> {
+        let text = self.render(params)?;
+        let dst = params.target.path(&self.dst);
+        Ok(write_action(params, dst, text.as_bytes(), self.mode))
+    }
+}
+
+
+impl Plannable for ShellTask {
+    fn plan(&self, _: &Params) -> Result<Action> {
+        Ok(Action::Run(self.0.clone()))
+    }
+}
+
+
+// write `contents` to `dst` only if it differs from what is already there
+fn deploy<F>(params: &Params, dst: &Path, contents: &[u8], mode: Option<u32>,
+             write: F) -> Result<Status>
+where
+    F: FnOnce(Option<&Superuser>) -> io::Result<()>,
+{
+    let su = params.superuser();
+    let state = filesystem::compare(dst, contents, mode);
+
+    let result = match state {
+        FileState::InSync => return Ok(Status::Unchanged),
+        FileState::Modified if !review(params, dst, contents) =>
+            return Ok(Status::Skipped),
+        FileState::WrongMode => filesystem::set_permissions(dst, mode, su),
+        FileState::Missing => filesystem::create_valid_parent(dst, su)
+            .and_then(|_| write(su))
+            .and_then(|_| filesystem::set_permissions(dst, mode, su)),
+        FileState::Modified => params.backups.save(dst, su)
+            .and_then(|_| write(su))
+            .and_then(|_| filesystem::set_permissions(dst, mode, su)),
+    };
+
+    result.map(|_| Status::Changed).map_err(|e| Error::io(dst, e))
+}
+
+
+fn record_file(params: &mut Params, status: Status, dst: &Path,
+               contents: &[u8]) -> Status {
+    if let (Status::Changed | Status::Unchanged, Ok(metadata))
+            = (&status, dst.metadata()) {
+        let managed = Managed::File {
+            hash: state::hash(contents),
+            mode: metadata.permissions().mode() & 0o7777,
+        };
+        let task = params.task_path();
+        params.ledger.record(dst, task, params.elevated, managed);
+    }
+    status
+}
+
+
+fn record_link(params: &mut Params, status: Status, dst: &Path, src: PathBuf)
+    -> Status
+{
+    let task = params.task_path();
+    params.ledger.record(dst, task, params.elevated,
+                         Managed::Symlink { target: src });
+    status
+}
+
+
+// returns whether the reviewed write to `dst` should go ahead
+fn review(params: &Params, dst: &Path, contents: &[u8]) -> bool {
+    !params.diff
+        || diff::review(dst, contents, params.confirm && !params.dry_run)
+}
+
+
+fn occupied(dst: &Path) -> Error {
+    Error::io(dst, io::Error::new(io::ErrorKind::AlreadyExists,
+                                  "already exists and is not a symlink"))
+}
+
+
+// the fields each task type accepts, checked in strict mode
+fn task_fields(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "copy" | "template" => &["src", "dst", "mode"],
+        "symlink" => &["src", "dst", "on_conflict"],
+        _ => &[],
+    }
+}
+
+
+fn quoted(words: &[&str]) -> String {
+    words.iter().map(|w| format!("`{}`", w)).collect::<Vec<_>>().join(", ")
+}
+
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() { name.to_owned() } else { format!("{}/{}", path, name) }
+}
+
+
+fn write_action(params: &Params, dst: PathBuf, contents: &[u8],
+                mode: Option<u32>) -> Action {
+    match filesystem::compare(&dst, contents, mode) {
+        FileState::InSync => Action::Unchanged(dst),
+        FileState::WrongMode => Action::Chmod(dst, mode.unwrap()),
+        FileState::Missing => Action::Create(dst),
+        FileState::Modified => {
+            review(params, &dst, contents);
+            Action::Overwrite(dst)
+        }
     }
 }
 
 
 impl fmt::Display for Status {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", match self {
-            Self::Success => "SUCCESS",
-            Self::Failure => "FAILURE",
+            Self::Changed => "CHANGED",
+            Self::Unchanged => "OK",
+            Self::Failure(_) => "FAILURE",
             Self::Skipped => "SKIPPED",
         })
     }
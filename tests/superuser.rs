@@ -0,0 +1,151 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+
+const TASKS: &str = "
+- name: copy
+  su: true
+  copy: {src: a.txt, dst: OUT/a.txt}
+- name: template
+  su: true
+  template: {src: t.conf, dst: OUT/t.conf}
+- name: symlink
+  su: true
+  symlink: {src: a.txt, dst: OUT/link}
+- name: shell
+  su: true
+  shell: echo hi
+";
+
+// expires the cached credentials of the fake once run as the superuser
+const EXPIRING: &str = "
+- name: expire
+  su: true
+  shell: touch OUT/../expired
+- name: copy
+  su: true
+  copy: {src: a.txt, dst: OUT/a.txt}
+";
+
+// logs its arguments, then runs the command like the real thing would once
+// authenticated, refusing to when `-n` is given and `cache` is false or the
+// credentials have expired
+const FAKE: &str = "#!/bin/sh
+dir=\"$(dirname \"$0\")/..\"
+echo \"$*\" >> \"$dir/su.log\"
+if [ \"$1\" = -n ]; then
+    if [ CACHE != true ] || [ -e \"$dir/expired\" ]; then
+        rm -f \"$dir/expired\"
+        exit 1
+    fi
+    shift
+fi
+case \"$1\" in
+    -v|true) exit 0;;
+esac
+exec \"$@\"
+";
+
+
+// a config dir with `tasks` deploying into `out`, and a fake `method` on
+// PATH
+fn sandbox(name: &str, method: &str, cache: bool, tasks: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("zapp-{}-{}", name,
+                                           std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for sub in ["bin", "out", "home", "cfg/tasks", "cfg/files",
+                "cfg/templates"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+
+    let out = dir.join("out");
+    let fake = dir.join("bin").join(method);
+    fs::write(&fake, FAKE.replace("CACHE", &cache.to_string())).unwrap();
+    fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("cfg/config.yaml"),
+              format!("su_method: {}\ntasks: [sys]\n", method)).unwrap();
+    fs::write(dir.join("cfg/tasks/sys.yaml"),
+              tasks.replace("OUT", &out.to_string_lossy())).unwrap();
+    fs::write(dir.join("cfg/files/a.txt"), "a\n").unwrap();
+    fs::write(dir.join("cfg/templates/t.conf"), "t\n").unwrap();
+    dir
+}
+
+
+fn apply(dir: &Path) -> bool {
+    let path = format!("{}:{}", dir.join("bin").display(),
+                       env::var("PATH").unwrap_or_default());
+    Command::new(env!("CARGO_BIN_EXE_zapp"))
+            .args(["--config-dir", &dir.join("cfg").to_string_lossy(),
+                   "apply", "-q"])
+            .env("PATH", path)
+            .env("HOME", dir.join("home"))
+            .env("XDG_STATE_HOME", dir.join("state"))
+            .output().unwrap().status.success()
+}
+
+
+fn log(dir: &Path) -> Vec<String> {
+    let log = fs::read_to_string(dir.join("su.log")).unwrap_or_default();
+    log.lines().map(|l| l.replace(&*dir.to_string_lossy(), "DIR")).collect()
+}
+
+
+// prompting once, then checking the cached credentials before every
+// command after the first
+fn privileged(auth: &str, refresh: &str) -> Vec<String> {
+    [auth,
+     refresh,
+     "-n cp DIR/cfg/files/a.txt DIR/out/a.txt",
+     refresh,
+     "-n sh -c cat > \"$0\" DIR/out/t.conf",
+     refresh,
+     "-n ln -s DIR/cfg/files/a.txt DIR/out/link",
+     refresh,
+     "-n /usr/bin/sh -c echo hi"].iter().map(|l| l.to_string()).collect()
+}
+
+
+#[test]
+fn sudo_authenticates_once_for_every_task_type() {
+    let dir = sandbox("sudo", "sudo", true, TASKS);
+    assert!(apply(&dir));
+    assert_eq!(log(&dir), privileged("-v", "-n -v"));
+    assert_eq!(fs::read_to_string(dir.join("out/t.conf")).unwrap(), "t\n");
+    assert!(dir.join("out/link").symlink_metadata().is_ok());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+
+#[test]
+fn doas_authenticates_once_for_every_task_type() {
+    let dir = sandbox("doas", "doas", true, TASKS);
+    assert!(apply(&dir));
+    assert_eq!(log(&dir), privileged("true", "-n true"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+
+#[test]
+fn sudo_prompts_again_once_credentials_expire() {
+    let dir = sandbox("expire", "sudo", true, EXPIRING);
+    assert!(apply(&dir));
+    assert_eq!(log(&dir), ["-v", "-n -v",
+                           "-n /usr/bin/sh -c touch DIR/out/../expired",
+                           "-n -v", "-v", "-n -v",
+                           "-n cp DIR/cfg/files/a.txt DIR/out/a.txt"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+
+#[test]
+fn doas_without_persist_is_refused() {
+    let dir = sandbox("nopersist", "doas", false, TASKS);
+    assert!(!apply(&dir));
+    assert_eq!(log(&dir), ["true", "-n true"]);
+    assert!(!dir.join("out/a.txt").exists());
+    fs::remove_dir_all(dir).unwrap();
+}